@@ -0,0 +1,45 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Records the `nsm-nitro-enclave-utils` version Cargo resolved, for
+//! `nsm_driver_version`, as the `NSM_UTILS_VERSION` environment variable
+//!
+//! The version is read from `Cargo.lock`, which is committed so that every
+//! build, including one from a fresh checkout or vendored sources, resolves
+//! and reports the same version.
+
+const PACKAGE: &str = "nsm-nitro-enclave-utils";
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = std::fs::read_to_string("Cargo.lock").unwrap_or_default();
+    let version = lock
+        .split("[[package]]")
+        .find(|package| package.contains(&format!("name = \"{PACKAGE}\"\n")))
+        .and_then(|package| {
+            package
+                .lines()
+                .find_map(|line| line.strip_prefix("version = "))
+        })
+        .map(|version| version.trim_matches('"'));
+    let version = version.unwrap_or_else(|| {
+        println!(
+            "cargo:warning={PACKAGE} not found in Cargo.lock, reporting its version as unknown"
+        );
+        "unknown"
+    });
+    println!("cargo:rustc-env=NSM_UTILS_VERSION={version}");
+}
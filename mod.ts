@@ -23,6 +23,10 @@
  * functionality through Rust FFI bindings. It allows you to generate cryptographically
  * signed attestation documents from within AWS Nitro Enclaves.
  *
+ * Structured values cross the FFI boundary as CBOR in both directions,
+ * the same encoding as attestation documents, so they decode identically
+ * on every architecture.
+ *
  * @example
  * ```typescript
  * import { attest, validate } from "./mod.ts";
@@ -36,21 +40,1049 @@
 
 import { decode, encode } from "cbor-x";
 
-import { attest as bAttest } from "./bindings/bindings.ts";
+import {
+  attest as bAttest,
+  attest_and_sign as bAttestAndSign,
+  attest_async as bAttestAsync,
+  attest_batch as bAttestBatch,
+  attest_compressed as bAttestCompressed,
+  attest_framed as bAttestFramed,
+  attest_hashed as bAttestHashed,
+  attest_key as bAttestKey,
+  attest_tls_cert as bAttestTlsCert,
+  attest_tagged as bAttestTagged,
+  attest_with_fresh_nonce as bAttestWithFreshNonce,
+  boot_session_id as bBootSessionId,
+  build_snapshot as bBuildSnapshot,
+  close_driver as bCloseDriver,
+  configure as bConfigure,
+  current_options as bCurrentOptions,
+  decompress_document as bDecompressDocument,
+  deframe as bDeframe,
+  document_boot_session_id as bDocumentBootSessionId,
+  document_chain_len as bDocumentChainLen,
+  document_fingerprint as bDocumentFingerprint,
+  document_is_fresh as bDocumentIsFresh,
+  document_key_commitment as bDocumentKeyCommitment,
+  document_pcr as bDocumentPcr,
+  document_timestamp as bDocumentTimestamp,
+  explain_verification as bExplainVerification,
+  in_enclave as bInEnclave,
+  init_driver as bInitDriver,
+  last_error as bLastError,
+  last_error_message as bLastErrorMessage,
+  nonzero_pcrs as bNonzeroPcrs,
+  nsm_driver_version as bNsmDriverVersion,
+  require_digest as bRequireDigest,
+  same_instance as bSameInstance,
+  self_check as bSelfCheck,
+  set_batch_limits as bSetBatchLimits,
+  set_request_id as bSetRequestId,
+  signing_algorithm as bSigningAlgorithm,
+  stats as bStats,
+  supported_requests as bSupportedRequests,
+  validate_pcr_banks as bValidatePcrBanks,
+  verify_against_snapshot as bVerifyAgainstSnapshot,
+  verify_bound_public_key as bVerifyBoundPublicKey,
+  verify_digest_consistency as bVerifyDigestConsistency,
+  verify_signature as bVerifySignature,
+  verify_tls_cert_binding as bVerifyTlsCertBinding,
+  verify_user_data_hash as bVerifyUserDataHash,
+  verify_with_policy as bVerifyWithPolicy,
+  verify_with_policy_cbor as bVerifyWithPolicyCbor,
+  verify_attestation as bVerifyAttestation,
+  verify_attestation_chain as bVerifyAttestationChain,
+  verify_and_export_key as bVerifyAndExportKey,
+  verify_batch as bVerifyBatch,
+} from "./bindings/bindings.ts";
+import * as bindings from "./bindings/bindings.ts";
+
+/**
+ * Status codes returned by the native bindings
+ *
+ * Mirrors `Error::code` in `src/lib.rs`
+ */
+export const ErrorCode = {
+  OK: 0,
+  MALFORMED_DOCUMENT: 1,
+  INVALID_ROOT: 2,
+  CHAIN_INVALID: 3,
+  SIGNATURE_INVALID: 4,
+  INVALID_ARGUMENT: 5,
+  BATCH_TOO_LARGE: 6,
+  POLICY_VIOLATION: 7,
+  ATTESTATION_FAILED: 8,
+  CERT_EXPIRED: 9,
+  CERT_NOT_YET_VALID: 10,
+  DRIVER_UNAVAILABLE: 11,
+  RATE_LIMITED: 12,
+  CHAIN_BROKEN: 13,
+  CANCELLED: 14,
+  INPUT_TOO_LARGE: 15,
+  QUOTA_EXCEEDED: 16,
+  UNEXPECTED_RESPONSE: 17,
+  FRAME_CORRUPT: 18,
+  NONCE_REPLAYED: 19,
+  DIGEST_MISMATCH: 20,
+  PCR_COUNT_INVALID: 21,
+  PCR_INDEX_OUT_OF_RANGE: 22,
+  PCR_INDEX_DUPLICATE: 23,
+  PCR_LENGTH_MISMATCH: 24,
+} as const;
+
+/**
+ * COSE algorithm identifiers (RFC 9053) attestations may be signed with
+ */
+export const CoseAlgorithm = {
+  ES256: -7,
+  ES384: -35,
+  ES512: -36,
+} as const;
+
+/**
+ * COSE algorithm identifiers (RFC 9054) of the hashes PCRs may be computed with
+ */
+export const DigestAlgorithm = {
+  SHA256: -16,
+  SHA384: -43,
+  SHA512: -44,
+} as const;
+
+/**
+ * Thrown when a native binding reports a failure
+ */
+export class NitroError extends Error {
+  /** The `ErrorCode` reported by the binding */
+  readonly code: number;
+
+  constructor(code: number, detail?: string) {
+    const name = Object.entries(ErrorCode).find(([, value]) => value === code)
+      ?.[0];
+    super(
+      `deno-nitro failed with ${name ?? code}${detail ? `: ${detail}` : ""}`,
+    );
+    this.name = "NitroError";
+    this.code = code;
+  }
+}
+
+/**
+ * Options accepted by `configure`; omitted options keep their current value
+ */
+export interface InitOptions {
+  /**
+   * Largest number of items in one `attestBatch` or `verifyBatch` call
+   * (default 1024)
+   */
+  batchMaxItems?: number;
+  /**
+   * Largest total encoded size of one `attestBatch` or `verifyBatch` call
+   * (default 16 MiB)
+   */
+  batchMaxBytes?: number;
+  /**
+   * One of `off`, `error`, `warn`, `info`, `debug` or `trace`
+   * (defaults to `DENO_NITRO_LOG`, or `warn`)
+   */
+  logLevel?: string;
+  /**
+   * Most `attestAsync` calls sent to the NSM at once; extra calls wait their
+   * turn (defaults to the number of CPUs, capped at the device concurrency)
+   */
+  attestPoolSize?: number;
+  /**
+   * How long, in milliseconds, the certificate chain and signature result of
+   * verifying a document is cached (`0`, the default, disables the cache).
+   * Time-sensitive checks such as age always run live.
+   */
+  verificationCacheTtlMs?: number;
+  /** Most verification results cached at once (default 1024) */
+  verificationCacheSize?: number;
+  /**
+   * Also reject documents whose leaf or intermediate certificates are outside
+   * their validity window at verification time, with `CERT_EXPIRED` or
+   * `CERT_NOT_YET_VALID`, even if otherwise valid (default `false`)
+   */
+  strictCertValidity?: boolean;
+  /**
+   * Milliseconds by which validity windows are stretched either side under
+   * `strictCertValidity`, and by which attestation timestamps may be ahead of
+   * the clock in age checks, to allow for clock skew (default 60000)
+   */
+  certClockSkewMs?: number;
+  /**
+   * Wrap the user data of every attestation as
+   * `{ bootSessionId, userData }`, where `userData` holds the bytes it would
+   * otherwise have been, so relying parties can spot enclave restarts
+   * with `documentBootSessionId` (default `false`). Attestations without user
+   * data, such as those of `attestKey`, are left without it
+   */
+  embedBootSessionId?: boolean;
+  /**
+   * Most attestations a caller tag (see `AttestOptions`) can make in a burst;
+   * `0`, the default, disables rate limiting
+   */
+  rateLimitCapacity?: number;
+  /** Attestations each tag's allowance regains per second (default 1) */
+  rateLimitRefillPerSecond?: number;
+  /**
+   * Accept documents with any number of PCRs, of any length, for
+   * NSM-compatible modules that do not follow the AWS Nitro conventions
+   * (default `false`). Signatures and chains are still verified, but this
+   * weakens policy: only enable it for modules known not to be AWS Nitro
+   */
+  relaxPcrChecks?: boolean;
+  /**
+   * Most decoded attestations kept, so that inspecting or verifying one
+   * attestation several times decodes it once; `0`, the default, disables
+   * the cache
+   */
+  documentCacheSize?: number;
+  /**
+   * Largest attestation, root, snapshot or policy, in bytes, that is parsed at
+   * all; larger inputs are rejected with `INPUT_TOO_LARGE` before anything is
+   * decoded from them (default 1048576)
+   */
+  maxInputBytes?: number;
+  /**
+   * Most nonces fetched from the device ahead of time for
+   * `attestWithFreshNonce`, refilled in the background whenever fewer than
+   * half remain, at most 4096; `0`, the default, fetches each nonce when it is
+   * needed
+   */
+  noncePoolSize?: number;
+  /**
+   * Most `user_data` bytes attested, across every caller, in any rolling
+   * `attestQuotaWindowMs`; attestations that would exceed it are refused with
+   * `QUOTA_EXCEEDED` (`0`, the default, sets no quota)
+   */
+  attestQuotaBytes?: number;
+  /** Length of the `attestQuotaBytes` window in milliseconds (default 60000) */
+  attestQuotaWindowMs?: number;
+  /**
+   * Most nonces `verifyWithPolicy` remembers, rejecting a second attestation
+   * with an accepted nonce as `NONCE_REPLAYED`; `0`, the default, accepts a
+   * nonce any number of times
+   */
+  seenNonceCapacity?: number;
+  /** How long accepted nonces are remembered, in milliseconds (default 300000) */
+  seenNonceTtlMs?: number;
+}
+
+/**
+ * Set several options at once, typically at startup before `initDriver`
+ *
+ * Every option is validated before any is applied,
+ * so a rejected call leaves the configuration unchanged
+ * @param options The options to change
+ * @throws {NitroError}
+ * `INVALID_ARGUMENT`, describing the first invalid option
+ */
+export function configure(options: InitOptions): void {
+  const code = bConfigure(encode(options));
+  if (code !== ErrorCode.OK) {
+    throw new NitroError(code, bLastErrorMessage());
+  }
+}
+
+/**
+ * Read back the options currently in effect
+ * @return Every option with its current value
+ */
+export function currentOptions(): Required<InitOptions> {
+  return decode(bCurrentOptions());
+}
+
+/**
+ * Set up the attestation driver now rather than on the first attestation
+ * @throws {NitroError}
+ * `DRIVER_UNAVAILABLE` if it cannot be set up, e.g. because the development
+ * driver's intermediate certificates are out of order
+ */
+export function initDriver(): void {
+  const code = bInitDriver();
+  if (code !== ErrorCode.OK) {
+    throw new NitroError(code, bLastErrorMessage());
+  }
+}
+
+/**
+ * Get the development driver's CA certificates, so tests and local verifiers
+ * can trust its attestations without reading its PKI files
+ *
+ * Only available when the library is built with the development driver
+ * @return
+ * DER certificates, root first and ending with the end certificate's issuer
+ * @throws {NitroError}
+ * `DRIVER_UNAVAILABLE` if the library was built for real enclaves, or the
+ * development driver could not be set up
+ */
+export function devCaChain(): Uint8Array[] {
+  // Only exported by development builds of the bindings
+  const bDevCaChain = (bindings as Record<string, unknown>).dev_ca_chain as
+    | (() => Uint8Array)
+    | undefined;
+  if (!bDevCaChain) {
+    throw new NitroError(
+      ErrorCode.DRIVER_UNAVAILABLE,
+      "not built with the development driver",
+    );
+  }
+  const chain = bDevCaChain();
+  if (chain.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return decode(chain);
+}
+
+/**
+ * Get a fixed attestation, identical on every call and platform, for golden
+ * test vectors shared with other implementations
+ *
+ * For testing only: it is signed with the public development PKI in
+ * `test_data/`, and only available when the library is built with the
+ * development driver
+ * @return The COSE_Sign1 attestation
+ * @throws {NitroError} `DRIVER_UNAVAILABLE` if the library was built for real
+ * enclaves
+ */
+export function emitTestVector(): Uint8Array {
+  // Only exported by development builds of the bindings
+  const bEmitTestVector = (bindings as Record<string, unknown>)
+    .emit_test_vector as (() => Uint8Array) | undefined;
+  if (!bEmitTestVector) {
+    throw new NitroError(
+      ErrorCode.DRIVER_UNAVAILABLE,
+      "not built with the development driver",
+    );
+  }
+  return bEmitTestVector();
+}
+
+/**
+ * Check the NSM reports the same PCR digest to `DescribeNSM` as its
+ * attestations declare, to catch firmware or driver mismatches
+ *
+ * Makes one attestation with canary user data to compare against
+ * @throws {NitroError}
+ * `DIGEST_MISMATCH` if the two disagree, otherwise the code of the step that
+ * failed, e.g. `DRIVER_UNAVAILABLE` or `ATTESTATION_FAILED`
+ */
+export function verifyDigestConsistency(): void {
+  const code = bVerifyDigestConsistency();
+  if (code !== ErrorCode.OK) {
+    throw new NitroError(code, bLastErrorMessage());
+  }
+}
+
+/**
+ * Freeze the clock attestation timestamps are checked against, so tests of
+ * `documentIsFresh`, `maxAgeMs` and `strictCertValidity` are deterministic
+ *
+ * For testing only: attestations the driver makes still carry the real time.
+ * @param [unixMs] Milliseconds since the Unix epoch to freeze the clock at, or
+ * omitted to go back to the system clock
+ * @throws {NitroError} `DRIVER_UNAVAILABLE` if the library was built for real
+ * enclaves
+ */
+export function setTestClock(unixMs?: number): void {
+  // Only exported by development builds of the bindings
+  const bSetTestClock = (bindings as Record<string, unknown>)
+    .set_test_clock as ((unixMs: number) => number) | undefined;
+  if (!bSetTestClock) {
+    throw new NitroError(
+      ErrorCode.DRIVER_UNAVAILABLE,
+      "not built with the development driver",
+    );
+  }
+  bSetTestClock(unixMs ?? 0);
+}
+
+/**
+ * Check the whole pipeline works before serving traffic: make one attestation
+ * with canary user data, then parse and verify it, against the development CA
+ * with the development driver and the default root otherwise
+ * @throws {NitroError}
+ * The code of the first step that failed, e.g. `DRIVER_UNAVAILABLE`,
+ * `ATTESTATION_FAILED` or `CHAIN_INVALID`
+ */
+export function selfCheck(): void {
+  const code = bSelfCheck();
+  if (code !== ErrorCode.OK) {
+    throw new NitroError(code, bLastErrorMessage());
+  }
+}
+
+/**
+ * Serve attestations to other processes on this host over a Unix domain
+ * socket, for components that cannot use these bindings
+ *
+ * Each request is a 4 byte big-endian length and a CBOR map with the optional
+ * byte strings `userData`, `publicKey` and `nonce`; each response a 4 byte
+ * big-endian status (an `ErrorCode`), a 4 byte big-endian length and the
+ * attestation, or the error message. The socket is only accessible to the
+ * user running this process (mode 0600).
+ * @param path Where to create the socket, replacing a stale one
+ * @throws {NitroError}
+ * `DRIVER_UNAVAILABLE` if the library was built without the `socket` feature,
+ * or `INVALID_ARGUMENT` if already serving or the socket cannot be bound
+ */
+export function serveSocket(path: string): void {
+  // Only exported by builds with the socket feature
+  const bServeSocket = (bindings as Record<string, unknown>).serve_socket as
+    | ((path: string) => number)
+    | undefined;
+  if (!bServeSocket) {
+    throw new NitroError(
+      ErrorCode.DRIVER_UNAVAILABLE,
+      "not built with the socket feature",
+    );
+  }
+  const code = bServeSocket(path);
+  if (code !== ErrorCode.OK) {
+    throw new NitroError(code, bLastErrorMessage());
+  }
+}
+
+/**
+ * Options accepted by `attest`
+ */
+export interface AttestOptions {
+  /**
+   * Logical caller (e.g. tenant) to charge the attestation to,
+   * for rate limiting with `rateLimitCapacity`
+   */
+  tag?: string;
+  /**
+   * Identifier (e.g. a trace ID) included in every log line emitted while
+   * processing the attestation, to pick them out among many
+   */
+  requestId?: string;
+}
 
 /**
  * Create an attestation with arbitrary serializeable input data
  * @param [input]
  * Arbitrary input data to include as `user_data` in the attestation
+ * @param [options] Optional caller tag and request ID
  * @return
  * COSE_Sign1 attestation as a Uint8Array
+ * @throws {NitroError}
+ * `QUOTA_EXCEEDED` if the input does not fit in the `attestQuotaBytes` left,
+ * `UNEXPECTED_RESPONSE` if the NSM answered with something other than an
+ * attestation, `RATE_LIMITED` if `options.tag` has used up its allowance,
+ * or `ATTESTATION_FAILED` if the driver could not attest
  */
-export function attest(input?: unknown): Uint8Array {
+export function attest(input?: unknown, options?: AttestOptions): Uint8Array {
   /**
    * Turn arbitrary input into CBOR
    */
   const bytes = encode(input);
-  return bAttest(bytes);
+  if (!options?.requestId) {
+    return attestEncoded(bytes, options?.tag);
+  }
+  bSetRequestId(options.requestId);
+  try {
+    return attestEncoded(bytes, options.tag);
+  } finally {
+    bSetRequestId("");
+  }
+}
+
+function attestEncoded(bytes: Uint8Array, tag?: string): Uint8Array {
+  const attestation = tag === undefined
+    ? bAttest(bytes)
+    : bAttestTagged(tag, bytes);
+  if (attestation.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return attestation;
+}
+
+/**
+ * Create an attestation like `attest`, compressed for shipping over
+ * bandwidth-constrained links
+ *
+ * The result is raw DEFLATE (RFC 1951, no zlib or gzip header) and is not an
+ * attestation itself: decompress it with `decompressDocument` before verifying
+ * or decoding it
+ * @param [input]
+ * Arbitrary input data to include as `user_data` in the attestation
+ * @return The compressed COSE_Sign1 attestation
+ * @throws {NitroError}
+ * `ATTESTATION_FAILED` if the driver could not attest
+ */
+export function attestCompressed(input?: unknown): Uint8Array {
+  const compressed = bAttestCompressed(encode(input));
+  if (compressed.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return compressed;
+}
+
+/**
+ * Restore an attestation from `attestCompressed`
+ * @param compressed The raw DEFLATE compressed attestation
+ * @return The attestation, byte for byte as the enclave produced it
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if `compressed` is not raw DEFLATE, or inflates to more
+ * than 64 KiB
+ */
+export function decompressDocument(compressed: Uint8Array): Uint8Array {
+  const attestation = bDecompressDocument(compressed);
+  if (attestation.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return attestation;
+}
+
+/**
+ * Create an attestation like `attest`, framed for streams that carry one
+ * attestation after another
+ *
+ * The frame is the attestation's length as 4 big-endian bytes, the
+ * attestation, and optionally an 8 byte checksum over both, so a corrupt frame
+ * is caught before anything parses it; unframe it with `deframe`
+ * @param [input]
+ * Arbitrary input data to include as `user_data` in the attestation
+ * @param [options]
+ * @param [options.checksum] Whether to append the checksum trailer
+ * @return The framed COSE_Sign1 attestation
+ * @throws {NitroError}
+ * `ATTESTATION_FAILED` if the driver could not attest
+ */
+export function attestFramed(
+  input?: unknown,
+  { checksum = false }: { checksum?: boolean } = {},
+): Uint8Array {
+  const frame = bAttestFramed(encode(input), checksum ? 1 : 0);
+  if (frame.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return frame;
+}
+
+/**
+ * Take the attestation out of a frame from `attestFramed`, checking its
+ * checksum if it has one
+ * @param frame The framed attestation
+ * @return The attestation, byte for byte as the enclave produced it
+ * @throws {NitroError}
+ * `FRAME_CORRUPT` if the frame is truncated, has trailing bytes, or fails its
+ * checksum
+ */
+export function deframe(frame: Uint8Array): Uint8Array {
+  const attestation = bDeframe(frame);
+  if (attestation.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return attestation;
+}
+
+/**
+ * Prefix of every message the enclave's bound key signs in `attestAndSign`:
+ * the signature covers `CHALLENGE_CONTEXT` followed by the challenge
+ */
+export const CHALLENGE_CONTEXT = new TextEncoder().encode(
+  "deno-nitro bound key challenge v1\0",
+);
+
+/**
+ * Attest and prove possession of the enclave's bound key in one step
+ *
+ * The bound key is a P-384 key pair generated inside the enclave when first
+ * needed. Its private key never leaves enclave memory and is replaced every
+ * time the library is loaded; if your enclave keeps its own key elsewhere,
+ * attest that key with `public_key` yourself instead.
+ *
+ * The attestation embeds the bound public key (as a DER SubjectPublicKeyInfo)
+ * and `challenge` as its nonce, and the signature is over
+ * `CHALLENGE_CONTEXT` followed by `challenge`. A relying party verifies the
+ * attestation, then the signature with the embedded key.
+ * @param challenge The relying party's challenge
+ * @param [input] Arbitrary input data to include as `user_data`
+ * @return
+ * The attestation, and an ECDSA P-384 / SHA-384 signature in the 96 byte
+ * `r || s` form WebCrypto verifies
+ * @throws {NitroError} `ATTESTATION_FAILED` if the driver could not attest
+ */
+export function attestAndSign(
+  challenge: Uint8Array,
+  input?: unknown,
+): { attestation: Uint8Array; signature: Uint8Array } {
+  const signed = bAttestAndSign(challenge, encode(input));
+  if (signed.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return decode(signed);
+}
+
+/**
+ * Attest a public key alone, e.g. for TLS key attestation
+ *
+ * The attestation carries the key as `public_key`, the nonce if one is given,
+ * and no `user_data`
+ * @param publicKey DER encoded SubjectPublicKeyInfo of the key to attest
+ * @param [nonce] Nonce supplied by the relying party
+ * @return COSE_Sign1 attestation as a Uint8Array
+ * @throws {NitroError}
+ * `INVALID_ARGUMENT` if `publicKey` is not a SubjectPublicKeyInfo,
+ * or `ATTESTATION_FAILED` if the driver could not attest
+ */
+export function attestKey(
+  publicKey: Uint8Array,
+  nonce: Uint8Array = new Uint8Array(),
+): Uint8Array {
+  const attestation = bAttestKey(publicKey, nonce);
+  if (attestation.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return attestation;
+}
+
+/**
+ * Attest a payload of any size by its SHA-256 hash
+ *
+ * The attestation's `user_data` is the 32 byte SHA-256 of `payload`, which is
+ * sent alongside it; relying parties check the two match with
+ * `verifyUserDataHash`
+ * @param payload The bytes to vouch for
+ * @return COSE_Sign1 attestation as a Uint8Array
+ * @throws {NitroError} `ATTESTATION_FAILED` if the driver could not attest
+ */
+export function attestHashed(payload: Uint8Array): Uint8Array {
+  const attestation = bAttestHashed(payload);
+  if (attestation.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return attestation;
+}
+
+/**
+ * Attest a TLS certificate by its SHA-384 hash, binding the attestation to the
+ * exact certificate a server presents
+ *
+ * The attestation's `user_data` is the 48 byte SHA-384 of `certDer`, and it
+ * carries the nonce if one is given; relying parties check the certificate
+ * they were presented with `verifyTlsCertBinding`
+ * @param certDer DER encoded X.509 certificate to bind
+ * @param [nonce] Nonce supplied by the relying party
+ * @return COSE_Sign1 attestation as a Uint8Array
+ * @throws {NitroError}
+ * `INVALID_ARGUMENT` if `certDer` is not a certificate,
+ * or `ATTESTATION_FAILED` if the driver could not attest
+ */
+export function attestTlsCert(
+  certDer: Uint8Array,
+  nonce: Uint8Array = new Uint8Array(),
+): Uint8Array {
+  const attestation = bAttestTlsCert(certDer, nonce);
+  if (attestation.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return attestation;
+}
+
+/**
+ * Create an attestation with a fresh random nonce from the NSM
+ *
+ * The nonce, read back from the attestation's `nonce`, is never used twice.
+ * With `noncePoolSize` set (see `configure`) it comes from nonces fetched
+ * ahead of time, so no extra round-trip to the device is made.
+ * @param [input]
+ * Arbitrary input data to include as `user_data` in the attestation
+ * @return COSE_Sign1 attestation as a Uint8Array
+ * @throws {NitroError}
+ * `ATTESTATION_FAILED` if the driver could not provide a nonce or attest
+ */
+export function attestWithFreshNonce(input?: unknown): Uint8Array {
+  const attestation = bAttestWithFreshNonce(encode(input));
+  if (attestation.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return attestation;
+}
+
+/**
+ * Create an attestation without blocking the JS thread
+ *
+ * At most `attestPoolSize` (see `configure`) attestations run at once,
+ * the rest wait for their turn
+ * @param [input]
+ * Arbitrary input data to include as `user_data` in the attestation
+ * @param [options] Optional request ID, as for `attest`
+ * @return
+ * COSE_Sign1 attestation as a Uint8Array
+ * @throws {NitroError}
+ * `CANCELLED` if `closeDriver` was called before the attestation was made,
+ * `QUOTA_EXCEEDED` if the input does not fit in what is left of
+ * `attestQuotaBytes`, `UNEXPECTED_RESPONSE` if the driver answered with
+ * another kind of response, or `ATTESTATION_FAILED` if the driver could not
+ * attest
+ */
+export async function attestAsync(
+  input?: unknown,
+  options?: Pick<AttestOptions, "requestId">,
+): Promise<Uint8Array> {
+  // A 4 byte big-endian status, then the attestation or the error message
+  const result = await bAttestAsync(options?.requestId ?? "", encode(input));
+  const status = new DataView(result.buffer, result.byteOffset, 4)
+    .getInt32(0);
+  const body = result.subarray(4);
+  if (status !== ErrorCode.OK) {
+    throw new NitroError(status, new TextDecoder().decode(body));
+  }
+  return body;
+}
+
+/**
+ * Cancel every pending `attestAsync` call, for shutdown
+ *
+ * Calls waiting for the pool or the device reject at once with `CANCELLED`,
+ * as do any made afterwards, for the rest of the process. Synchronous calls
+ * are unaffected.
+ */
+export function closeDriver(): void {
+  bCloseDriver();
+}
+
+/**
+ * Create an attestation for each of several inputs in one call
+ * @param inputs
+ * Arbitrary input data, each included as `user_data` in its own attestation
+ * @return
+ * COSE_Sign1 attestations in the same order as `inputs`
+ * (empty where that attestation failed)
+ * @throws {NitroError}
+ * `BATCH_TOO_LARGE` if the batch exceeds the limits set by `setBatchLimits`
+ */
+export function attestBatch(inputs: unknown[]): Uint8Array[] {
+  const batch = encode(inputs.map((input) => encode(input)));
+  const documents = bAttestBatch(batch);
+  if (documents.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return decode(documents);
+}
+
+/**
+ * Set the ceilings `attestBatch` and `verifyBatch` enforce before handling
+ * any item
+ * @param maxItems Largest number of inputs in one batch (default 1024)
+ * @param maxBytes
+ * Largest total size of the CBOR encoded inputs in one batch (default 16 MiB)
+ */
+export function setBatchLimits(maxItems: number, maxBytes: number): void {
+  const code = bSetBatchLimits(maxItems, maxBytes);
+  if (code !== ErrorCode.OK) {
+    throw new NitroError(code);
+  }
+}
+
+/**
+ * Describe why the last failing call on this thread failed
+ *
+ * Set alongside the returned `ErrorCode` by calls such as `verifyAttestation`
+ * @return The reason, or an empty string if the last call succeeded
+ */
+export function lastErrorMessage(): string {
+  return bLastErrorMessage();
+}
+
+/**
+ * Find the algorithm the backend signs attestations with
+ *
+ * Useful in health checks to confirm the enclave signs with the expected
+ * algorithm (AWS Nitro Enclaves use `CoseAlgorithm.ES384`)
+ * @return
+ * The COSE algorithm identifier, or 0 if the backend cannot attest
+ */
+export function signingAlgorithm(): number {
+  return bSigningAlgorithm();
+}
+
+/**
+ * Check whether this process is running in a real Nitro Enclave
+ *
+ * Probes the NSM once; always `false` with the development driver.
+ * Use it to refuse enclave-only work anywhere else.
+ * @return Whether the NSM is present and responding
+ */
+export function inEnclave(): boolean {
+  return bInEnclave() === 1;
+}
+
+/**
+ * Describe the NSM driver the backend links against
+ *
+ * Names the `nsm-nitro-enclave-utils` version and, in an enclave, the NSM
+ * protocol version the device reports; useful in bug reports.
+ * @return e.g. `"nsm-nitro-enclave-utils 0.1.3; NSM 1.0.0"`
+ */
+export function nsmDriverVersion(): string {
+  return bNsmDriverVersion();
+}
+
+/**
+ * Requests defined by the NSM API
+ */
+export type NsmRequest =
+  | "Attestation"
+  | "DescribeNSM"
+  | "DescribePCR"
+  | "ExtendPCR"
+  | "GetRandom"
+  | "LockPCR"
+  | "LockPCRs";
+
+/**
+ * List the NSM requests this build of the library wraps
+ *
+ * Lets callers detect features up front,
+ * e.g. when running against an older or newer version of the library
+ * @return The supported requests
+ */
+export function supportedRequests(): NsmRequest[] {
+  return decode(bSupportedRequests());
+}
+
+/**
+ * Check an attestation was created recently enough, without verifying it
+ *
+ * Only the embedded timestamp is checked, so this is meant for deciding
+ * whether to reuse a cached attestation that has already been verified
+ * with `verifyAttestation`
+ * @param attestation The previously verified COSE_Sign1 attestation
+ * @param maxAgeMs Greatest acceptable age in milliseconds
+ * @return
+ * Whether the attestation is at most `maxAgeMs` old and at most
+ * `certClockSkewMs` in the future (false if it cannot be decoded)
+ */
+export function documentIsFresh(
+  attestation: Uint8Array,
+  maxAgeMs: number,
+): boolean {
+  return bDocumentIsFresh(attestation, maxAgeMs) === 1;
+}
+
+/**
+ * Compute a short, stable identifier for an attestation
+ *
+ * The fingerprint is the SHA-256 of the attestation bytes exactly as given,
+ * so it is reproducible with any SHA-256 implementation and never changes
+ * between versions. The attestation is not verified.
+ * @param attestation The COSE_Sign1 attestation to fingerprint
+ * @return The 32 byte SHA-256 digest
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if `attestation` is not an attestation document
+ */
+export function documentFingerprint(attestation: Uint8Array): Uint8Array {
+  const fingerprint = bDocumentFingerprint(attestation);
+  if (fingerprint.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return fingerprint;
+}
+
+/**
+ * Compute a commitment to the key an attestation binds, for transcripts
+ *
+ * The commitment is the SHA-256 of the attestation's `public_key` bytes
+ * exactly as attested (the DER SubjectPublicKeyInfo for `attestKey`), or 32
+ * zero bytes if it binds no key. The attestation is not verified.
+ * @param attestation The COSE_Sign1 attestation binding the key
+ * @return The 32 byte commitment
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if `attestation` is not an attestation document
+ */
+export function documentKeyCommitment(attestation: Uint8Array): Uint8Array {
+  const commitment = bDocumentKeyCommitment(attestation);
+  if (commitment.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return commitment;
+}
+
+/**
+ * Get this process's boot session id
+ *
+ * The id is 16 random bytes chosen once per process, so it changes whenever
+ * the enclave restarts. See `embedBootSessionId` in `InitOptions`.
+ * @return The boot session id
+ */
+export function bootSessionId(): Uint8Array {
+  return bBootSessionId();
+}
+
+/**
+ * Read the boot session id embedded in an attestation, without verifying it
+ *
+ * Two attestations with the same id came from the same running enclave
+ * @param attestation The COSE_Sign1 attestation to inspect
+ * @return
+ * The id, or `undefined` if the attestation was made without
+ * `embedBootSessionId`
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if `attestation` is not an attestation document
+ */
+export function documentBootSessionId(
+  attestation: Uint8Array,
+): Uint8Array | undefined {
+  const id = bDocumentBootSessionId(attestation);
+  if (id.length === 0) {
+    if (bLastError() !== ErrorCode.OK) {
+      throw new NitroError(bLastError(), bLastErrorMessage());
+    }
+    return undefined;
+  }
+  return id;
+}
+
+/**
+ * Count the certificates in an attestation's chain, without verifying it
+ *
+ * A cheap pre-filter for verifiers expecting a particular chain depth
+ * @param attestation The COSE_Sign1 attestation to inspect
+ * @return The number of certificates: the leaf plus those in its cabundle
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if the attestation cannot be decoded
+ */
+export function documentChainLen(attestation: Uint8Array): number {
+  const length = bDocumentChainLen(attestation);
+  if (length < 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return length;
+}
+
+/**
+ * Read when an attestation was created, without verifying it
+ * @param attestation The COSE_Sign1 attestation to inspect
+ * @return Its timestamp, in milliseconds since the Unix epoch
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if the attestation cannot be decoded
+ */
+export function documentTimestamp(attestation: Uint8Array): number {
+  const timestamp = Number(bDocumentTimestamp(attestation));
+  if (timestamp < 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return timestamp;
+}
+
+/**
+ * Find which PCRs an attestation reports as set, without verifying it
+ *
+ * Debug enclaves report every PCR as zeros, so a mask of 0 indicates a debug
+ * attestation, while missing bits can reveal unexpectedly empty banks
+ * @param attestation The COSE_Sign1 attestation to inspect
+ * @return
+ * Bitmask with bit `i` set when PCR `i` (0-31) is not all zeros
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if the attestation cannot be decoded
+ */
+export function nonzeroPcrs(attestation: Uint8Array): number {
+  const mask = bNonzeroPcrs(attestation);
+  if (mask === 0 && bLastError() !== ErrorCode.OK) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return mask;
+}
+
+/**
+ * Read one PCR of an attestation, without verifying it or decoding the rest
+ * @param attestation The COSE_Sign1 attestation to inspect
+ * @param index The PCR's index (0-31)
+ * @return The PCR's value, or empty if the attestation has no such PCR
+ * @throws {NitroError}
+ * `INVALID_ARGUMENT` if `index` is out of range, or `MALFORMED_DOCUMENT` if
+ * the attestation cannot be decoded
+ */
+export function documentPcr(
+  attestation: Uint8Array,
+  index: number,
+): Uint8Array {
+  const value = bDocumentPcr(attestation, index);
+  if (value.length === 0 && bLastError() !== ErrorCode.OK) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return value;
+}
+
+/**
+ * Check every PCR in an attestation is well formed, without verifying it
+ *
+ * A cheap structural check to run before deeper verification: PCR indices
+ * must be distinct and below 32, and each PCR as long as a hash of the
+ * declared digest, even with `relaxPcrChecks` set
+ * @param attestation The COSE_Sign1 attestation to inspect
+ * @return
+ * `ErrorCode.OK` if every PCR is well formed, otherwise the code of the first
+ * anomaly: `PCR_COUNT_INVALID`, `PCR_INDEX_OUT_OF_RANGE`,
+ * `PCR_INDEX_DUPLICATE` or `PCR_LENGTH_MISMATCH` (`MALFORMED_DOCUMENT` if the
+ * attestation does not decode at all), with `lastErrorMessage()` naming it
+ */
+export function validatePcrBanks(attestation: Uint8Array): number {
+  return bValidatePcrBanks(attestation);
+}
+
+/**
+ * Check an attestation declares the required PCR digest algorithm,
+ * without verifying it
+ *
+ * For policies that demand a particular hash, e.g. SHA-384
+ * @param attestation The COSE_Sign1 attestation to inspect
+ * @param expected The required `DigestAlgorithm`
+ * @return Whether the attestation's `digest` is exactly `expected`
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if the attestation cannot be decoded,
+ * or `INVALID_ARGUMENT` if `expected` is not a `DigestAlgorithm`
+ */
+export function requireDigest(
+  attestation: Uint8Array,
+  expected: number,
+): boolean {
+  const matches = bRequireDigest(attestation, expected) === 1;
+  if (!matches && bLastError() !== ErrorCode.OK) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return matches;
+}
+
+/**
+ * Attestation counters and latencies since the library was loaded
+ */
+export interface Stats {
+  /** Attestation requests sent to the driver */
+  attestations: number;
+  /** Requests that did not produce an attestation */
+  failures: number;
+  /** Most `attestAsync` calls run at once since the pool was last resized */
+  peakAsyncAttestations: number;
+  /** Verifications whose chain and signature result came from the cache */
+  verificationCacheHits: number;
+  /** Decodes reused from the document cache */
+  documentCacheHits: number;
+  /**
+   * How long setting up the driver took, in microseconds (0 until it is set
+   * up, by `initDriver` or the first attestation)
+   */
+  driverInitMicros: number;
+  /** Nonces ready in the pool for `attestWithFreshNonce` */
+  pooledNonces: number;
+  /** Estimated latency percentiles and the exact maximum, in microseconds */
+  latencyMicros: { p50: number; p95: number; p99: number; max: number };
+}
+
+/**
+ * Read the attestation counters and latency percentiles
+ *
+ * Percentiles are streaming estimates, so they cost nothing to keep
+ * but are approximate
+ * @return The statistics so far
+ */
+export function stats(): Stats {
+  return decode(bStats());
 }
 
 function decodeCose(cose: Uint8Array) {
@@ -91,3 +1123,414 @@ export function validate(attestation: Uint8Array): {
   const cose = decodeCose(attestation);
   return cose;
 }
+
+/**
+ * Verify an attestation's certificate chain and signature
+ * @param attestation The COSE_Sign1 attestation to verify
+ * @param [root]
+ * DER encoded root certificate to verify against, or a PEM bundle of roots
+ * any of which may have issued the chain.
+ * Defaults to the file named by `DENO_NITRO_ROOT_CERT`,
+ * or the embedded AWS Nitro Enclaves root when that is unset.
+ * @return
+ * `ErrorCode.OK` if the attestation verifies, otherwise the reason it did not
+ */
+export function verifyAttestation(
+  attestation: Uint8Array,
+  root: Uint8Array = new Uint8Array(),
+): number {
+  return bVerifyAttestation(attestation, root);
+}
+
+/**
+ * Verify a chain of attestations in which each attester vouches for the last
+ *
+ * For nested enclaves or proxies: each attestation after the first must
+ * include the `documentFingerprint` of the one before it in its user data,
+ * e.g. `attest(documentFingerprint(inner))`. Every attestation must also pass
+ * `verifyAttestation` against `root`.
+ * @param attestations The attestations, innermost first
+ * @param [root]
+ * DER encoded root certificate to verify against, or a PEM bundle of roots,
+ * with the same default as `verifyAttestation`
+ * @return
+ * -1 if the whole chain verifies, otherwise the index of the first attestation
+ * that failed (`lastErrorMessage` gives the reason, `INPUT_TOO_LARGE` for one
+ * larger than `maxInputBytes`)
+ * @throws {NitroError}
+ * `INVALID_ARGUMENT` if `attestations` is empty, `BATCH_TOO_LARGE` if it
+ * exceeds the limits set by `setBatchLimits`, or `INVALID_ROOT` if `root` is
+ * invalid; no attestation is checked then
+ */
+export function verifyAttestationChain(
+  attestations: Uint8Array[],
+  root: Uint8Array = new Uint8Array(),
+): number {
+  const index = bVerifyAttestationChain(encode(attestations), root);
+  // The chain as a whole was rejected
+  if (index === -2) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return index;
+}
+
+/**
+ * Verification policy captured by `buildSnapshot`
+ */
+export interface SnapshotSpec {
+  /**
+   * DER encoded root certificate attestations must chain to, or a PEM bundle
+   * of roots any of which may have issued the chain
+   */
+  root: Uint8Array;
+  /** Accepted values by PCR index; PCRs not listed may hold anything */
+  pcrs?: Record<number, Uint8Array[]>;
+  /** Accepted module IDs (any if omitted or empty) */
+  moduleIds?: string[];
+  /** Greatest accepted attestation age in milliseconds (no limit if 0) */
+  maxAgeMs?: number;
+}
+
+/**
+ * Freeze a verification policy into one self-contained blob,
+ * e.g. to ship to an air-gapped verifier
+ *
+ * Equal policies always produce byte-identical snapshots
+ * @param spec The policy to capture
+ * @return The snapshot, for `verifyAgainstSnapshot`
+ * @throws {NitroError}
+ * `INVALID_ROOT` or `INVALID_ARGUMENT` if the policy is invalid
+ */
+export function buildSnapshot(spec: SnapshotSpec): Uint8Array {
+  const { pcrs = {}, ...rest } = spec;
+  const snapshot = bBuildSnapshot(encode({
+    ...rest,
+    // PCR indices are integer keys, as in attestations
+    pcrs: new Map(
+      Object.entries(pcrs).map(([index, values]) => [Number(index), values]),
+    ),
+  }));
+  if (snapshot.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return snapshot;
+}
+
+/**
+ * Verify an attestation against a snapshot from `buildSnapshot`
+ *
+ * Checks the certificate chain and signature against the snapshot's root,
+ * then its PCR, module ID and age constraints
+ * @param attestation The COSE_Sign1 attestation to verify
+ * @param snapshot The snapshot to verify against
+ * @return
+ * `ErrorCode.OK` if the attestation is accepted, otherwise the reason it was not
+ * (`ErrorCode.POLICY_VIOLATION` if it verified but broke a constraint)
+ */
+export function verifyAgainstSnapshot(
+  attestation: Uint8Array,
+  snapshot: Uint8Array,
+): number {
+  return bVerifyAgainstSnapshot(attestation, snapshot);
+}
+
+/**
+ * Check two attestations came from the same running enclave instance, e.g. to
+ * detect a restart between two handshakes
+ *
+ * Both must have the same `module_id` and boot session id (see
+ * `embedBootSessionId`). Attestations without a boot session id are compared
+ * by `module_id` alone, which names the enclave rather than the process, so a
+ * process restarted inside the same enclave goes unnoticed. The attestations
+ * are not verified, so check them with `verifyAttestation` first.
+ * @param a One COSE_Sign1 attestation
+ * @param b The other
+ * @return
+ * Whether they came from the same instance (false if only one of them has a
+ * boot session id)
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if either attestation cannot be decoded
+ */
+export function sameInstance(a: Uint8Array, b: Uint8Array): boolean {
+  const same = bSameInstance(a, b) === 1;
+  if (!same && bLastError() !== ErrorCode.OK) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return same;
+}
+
+/**
+ * Check the public key an attestation embeds is the one you expect,
+ * e.g. from a CSR or certificate the enclave presented
+ *
+ * The attestation itself is not verified, so check it with
+ * `verifyAttestation` first. Keys are compared in constant time.
+ * @param attestation The verified COSE_Sign1 attestation
+ * @param expectedSpki DER encoded SubjectPublicKeyInfo of the expected key
+ * @return
+ * Whether the attestation embeds exactly `expectedSpki`
+ * (false if it embeds no key, or a different one)
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if the attestation cannot be decoded,
+ * or `INVALID_ARGUMENT` if `expectedSpki` is not a SubjectPublicKeyInfo
+ */
+export function verifyBoundPublicKey(
+  attestation: Uint8Array,
+  expectedSpki: Uint8Array,
+): boolean {
+  const matches = bVerifyBoundPublicKey(attestation, expectedSpki) === 1;
+  if (!matches && bLastError() !== ErrorCode.OK) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return matches;
+}
+
+/**
+ * Check an attestation's user data is the SHA-256 hash of a payload, as
+ * `attestHashed` attests it
+ *
+ * The attestation itself is not verified, so check it with
+ * `verifyAttestation` first. Hashes are compared in constant time.
+ * @param attestation The verified COSE_Sign1 attestation
+ * @param payload The payload sent alongside the attestation
+ * @return Whether the attestation's user data is the hash of `payload`
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if the attestation cannot be decoded
+ */
+export function verifyUserDataHash(
+  attestation: Uint8Array,
+  payload: Uint8Array,
+): boolean {
+  const matches = bVerifyUserDataHash(attestation, payload) === 1;
+  if (!matches && bLastError() !== ErrorCode.OK) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return matches;
+}
+
+/**
+ * Check an attestation commits to a TLS certificate, as `attestTlsCert` binds
+ * it
+ *
+ * The attestation itself is not verified, so check it with
+ * `verifyAttestation` first. Hashes are compared in constant time.
+ * @param attestation The verified COSE_Sign1 attestation
+ * @param certDer DER encoded certificate the server presented
+ * @return Whether the attestation's user data is the hash of `certDer`
+ * @throws {NitroError}
+ * `MALFORMED_DOCUMENT` if the attestation cannot be decoded
+ */
+export function verifyTlsCertBinding(
+  attestation: Uint8Array,
+  certDer: Uint8Array,
+): boolean {
+  const matches = bVerifyTlsCertBinding(attestation, certDer) === 1;
+  if (!matches && bLastError() !== ErrorCode.OK) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return matches;
+}
+
+/**
+ * Verify a signature made with the key a trusted attestation binds, e.g. over
+ * application data signed by a key attested with `attestKey`
+ *
+ * The attestation is verified first, like `verifyAttestation`
+ * @param signedData The data that was signed; for `attestAndSign` signatures,
+ * `CHALLENGE_CONTEXT` followed by the challenge
+ * @param signature ECDSA P-384 / SHA-384 signature as 96 bytes of `r || s`,
+ * the format WebCrypto produces
+ * @param attestation The COSE_Sign1 attestation binding the signing key
+ * @param [root]
+ * DER encoded root certificate, or a PEM bundle of roots (the default root if
+ * omitted)
+ * @return
+ * `ErrorCode.OK` if the attestation verifies and its key made the signature,
+ * otherwise the reason it did not (`ErrorCode.POLICY_VIOLATION` if the
+ * attestation binds no ECDSA P-384 key)
+ */
+export function verifySignature(
+  signedData: Uint8Array,
+  signature: Uint8Array,
+  attestation: Uint8Array,
+  root: Uint8Array = new Uint8Array(),
+): number {
+  return bVerifySignature(signedData, signature, attestation, root);
+}
+
+/**
+ * Constraints checked by `verifyWithPolicy`; omitted ones accept anything
+ */
+export interface VerificationPolicy {
+  /**
+   * DER encoded root certificate, or a PEM bundle of roots (the default root
+   * if omitted)
+   */
+  root?: Uint8Array;
+  /** Accepted values by PCR index; PCRs not listed may hold anything */
+  pcrs?: Record<number, Uint8Array[]>;
+  /** Accepted module IDs (any if omitted or empty) */
+  moduleIds?: string[];
+  /** Nonce the attestation must carry, e.g. the challenge it answers */
+  nonce?: Uint8Array;
+  /** Greatest accepted attestation age in milliseconds (no limit if 0) */
+  maxAgeMs?: number;
+  /** Reject attestations from debug enclaves, whose PCRs are all zeros */
+  rejectDebug?: boolean;
+  /** Digest the PCRs must have been computed with, see `DigestAlgorithm` */
+  requiredDigest?: number;
+  /**
+   * DER SubjectPublicKeyInfos the leaf certificate's key must be one of, to pin
+   * the enclave's signing keys (any key the root vouches for if omitted or
+   * empty)
+   */
+  leafKeys?: Uint8Array[];
+}
+
+/**
+ * Verify an attestation and check it against a whole policy in one call
+ *
+ * Checks the certificate chain and signature, then the module ID, age, PCRs,
+ * nonce, debug mode, digest and leaf key constraints, in that order, and last,
+ * with `seenNonceCapacity` set, that the nonce was not accepted before
+ * @param attestation The COSE_Sign1 attestation to verify
+ * @param policy The constraints it must meet
+ * @return
+ * `ErrorCode.OK` if the attestation is accepted, otherwise the reason for the
+ * first failure (`ErrorCode.POLICY_VIOLATION` if it verified but broke a
+ * constraint, `ErrorCode.NONCE_REPLAYED` if its nonce was already accepted,
+ * `ErrorCode.INVALID_ARGUMENT` if the policy is invalid)
+ */
+export function verifyWithPolicy(
+  attestation: Uint8Array,
+  policy: VerificationPolicy,
+): number {
+  return bVerifyWithPolicy(attestation, encodePolicy(policy));
+}
+
+function encodePolicy({ pcrs = {}, ...rest }: VerificationPolicy): Uint8Array {
+  return encode({
+    ...rest,
+    // PCR indices are integer keys, as in attestations
+    pcrs: new Map(
+      Object.entries(pcrs).map(([index, values]) => [Number(index), values]),
+    ),
+  });
+}
+
+/**
+ * Outcome of `verifyWithPolicyCbor`
+ */
+export interface PolicyVerification {
+  /** `ErrorCode.OK`, or the reason for the first failure */
+  code: number;
+  /** Why the attestation was rejected (empty if it was accepted) */
+  message: string;
+  /** The accepted attestation's payload, or null if it was rejected */
+  document: {
+    moduleId: string;
+    /** Milliseconds since the Unix epoch */
+    timestamp: number;
+    /** Digest the PCRs were computed with, see `DigestAlgorithm` */
+    digest: number;
+    pcrs: Record<number, Uint8Array>;
+    userData: Uint8Array | null;
+    nonce: Uint8Array | null;
+    publicKey: Uint8Array | null;
+  } | null;
+}
+
+/**
+ * Like `verifyWithPolicy`, also returning the payload of an accepted
+ * attestation
+ *
+ * The outcome comes back from the native side as CBOR, so byte strings such
+ * as PCRs arrive as bytes, without a text encoding in between
+ * @param attestation The COSE_Sign1 attestation to verify
+ * @param policy The constraints it must meet
+ * @return The outcome, with the verified payload fields on success
+ */
+export function verifyWithPolicyCbor(
+  attestation: Uint8Array,
+  policy: VerificationPolicy,
+): PolicyVerification {
+  return decode(bVerifyWithPolicyCbor(attestation, encodePolicy(policy)));
+}
+
+/**
+ * Verify an attestation against a policy like `verifyWithPolicy` and export
+ * the public key bound into it, ready for
+ * `crypto.subtle.importKey("spki", ...)`
+ *
+ * Whether the enclave bound a DER SubjectPublicKeyInfo or a bare SEC1 P-384
+ * point, the key comes back as a DER SubjectPublicKeyInfo.
+ * @param attestation The COSE_Sign1 attestation to verify
+ * @param policy The constraints the attestation must meet
+ * @return The bound key as a DER SubjectPublicKeyInfo, or `null` if the
+ * attestation passed with no key bound
+ * @throws {NitroError}
+ * The code of the first check that failed, as `verifyWithPolicy` returns it,
+ * or `MALFORMED_DOCUMENT` if the bound key is in neither encoding
+ */
+export function verifyAndExportKey(
+  attestation: Uint8Array,
+  policy: VerificationPolicy,
+): Uint8Array | null {
+  const key = bVerifyAndExportKey(attestation, encodePolicy(policy));
+  if (key.length === 0) {
+    const code = bLastError();
+    if (code !== ErrorCode.OK) {
+      throw new NitroError(code, bLastErrorMessage());
+    }
+    return null;
+  }
+  return key;
+}
+
+/**
+ * Verify many attestations against one policy in a single call, for bulk jobs
+ * such as audits
+ *
+ * Each attestation is checked as by `verifyWithPolicy`, but the policy and its
+ * roots are parsed once for the whole batch; nonces are used up in order, so
+ * with `seenNonceCapacity` set a nonce repeated later in the batch is
+ * `NONCE_REPLAYED`
+ * @param attestations The COSE_Sign1 attestations to verify
+ * @param policy The constraints each must meet
+ * @return The `ErrorCode` for each attestation, in order: `ErrorCode.OK` if it
+ * is accepted, otherwise the reason for its first failure (`INPUT_TOO_LARGE`
+ * if it is larger than `maxInputBytes`)
+ * @throws {NitroError}
+ * `INVALID_ROOT` or `INVALID_ARGUMENT` if the policy is invalid, or
+ * `BATCH_TOO_LARGE` if the batch exceeds the limits set by `setBatchLimits`;
+ * no attestation is checked then
+ */
+export function verifyBatch(
+  attestations: Uint8Array[],
+  policy: VerificationPolicy,
+): number[] {
+  const codes = bVerifyBatch(encode(attestations), encodePolicy(policy));
+  if (codes.length === 0) {
+    throw new NitroError(bLastError(), bLastErrorMessage());
+  }
+  return decode(codes);
+}
+
+/**
+ * Explain how an attestation fares against a policy, for people reading logs
+ *
+ * The first line is the outcome `verifyWithPolicy` would report; each after
+ * it says whether the chain and signature, then each constraint in turn,
+ * passed, failed (and why) or was not checked. Failed PCRs show the accepted
+ * values and the attestation's, in hex. Nothing is shown that the attestation
+ * does not already make public.
+ * @param attestation The COSE_Sign1 attestation to verify
+ * @param policy The constraints it must meet
+ * @return The explanation, one line per check
+ */
+export function explainVerification(
+  attestation: Uint8Array,
+  policy: VerificationPolicy,
+): string {
+  return bExplainVerification(attestation, encodePolicy(policy));
+}
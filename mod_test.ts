@@ -25,9 +25,175 @@
  */
 
 import { Buffer } from "node:buffer";
-import { assertEquals, assertInstanceOf } from "@std/assert";
+import {
+  assertEquals,
+  assertInstanceOf,
+  assertNotEquals,
+  assertThrows,
+} from "@std/assert";
+import { decode, Decoder, encode, Encoder } from "cbor-x";
 
-import { attest, validate } from "./mod.ts";
+import {
+  attest,
+  attestAndSign,
+  attestCompressed,
+  attestFramed,
+  attestHashed,
+  attestTlsCert,
+  attestAsync,
+  attestBatch,
+  attestKey,
+  attestWithFreshNonce,
+  bootSessionId,
+  buildSnapshot,
+  CHALLENGE_CONTEXT,
+  configure,
+  CoseAlgorithm,
+  currentOptions,
+  decompressDocument,
+  deframe,
+  devCaChain,
+  DigestAlgorithm,
+  documentBootSessionId,
+  documentChainLen,
+  documentFingerprint,
+  documentIsFresh,
+  documentKeyCommitment,
+  documentPcr,
+  documentTimestamp,
+  emitTestVector,
+  ErrorCode,
+  explainVerification,
+  inEnclave,
+  initDriver,
+  type InitOptions,
+  type NsmRequest,
+  lastErrorMessage,
+  NitroError,
+  nonzeroPcrs,
+  nsmDriverVersion,
+  requireDigest,
+  sameInstance,
+  selfCheck,
+  serveSocket,
+  setBatchLimits,
+  setTestClock,
+  signingAlgorithm,
+  stats,
+  supportedRequests,
+  validate,
+  validatePcrBanks,
+  type VerificationPolicy,
+  verifyAgainstSnapshot,
+  verifyAttestation,
+  verifyAttestationChain,
+  verifyBoundPublicKey,
+  verifyDigestConsistency,
+  verifySignature,
+  verifyTlsCertBinding,
+  verifyUserDataHash,
+  verifyAndExportKey,
+  verifyBatch,
+  verifyWithPolicy,
+  verifyWithPolicyCbor,
+} from "./mod.ts";
+import * as bindings from "./bindings/bindings.ts";
+
+/**
+ * Set by `deno task test:dev`, which runs against the development driver
+ * and its test PKI in `test_data/`
+ */
+const dev = Deno.env.get("DENO_NITRO_DEV") === "1";
+
+/**
+ * Set by `deno task test:dev-embedded`,
+ * whose development driver has the test PKI built in
+ */
+const embedded = Deno.env.get("DENO_NITRO_DEV_EMBEDDED") === "1";
+
+// The driver loads its keys on first use,
+// so this must run before anything else attests
+Deno.test({
+  name: "devEmbeddedTest",
+  ignore: !embedded,
+  fn() {
+    const cwd = Deno.cwd();
+    Deno.chdir(Deno.makeTempDirSync());
+    try {
+      assertEquals(attest("no test_data here").length > 0, true);
+    } finally {
+      Deno.chdir(cwd);
+    }
+  },
+});
+
+/**
+ * Re-encode an attestation after editing its payload, keeping the original
+ * signature (so the result only passes checks that skip verification).
+ * `edit` may return raw payload bytes to use instead of the edited map.
+ */
+function withPayload(
+  attestation: Uint8Array,
+  edit: (payload: Map<unknown, unknown>) => Uint8Array | void,
+): Uint8Array {
+  // Keep integer map keys (e.g. PCR indices) intact
+  const decoder = new Decoder({ mapsAsObjects: false });
+  const [protectedHeaders, unprotectedHeaders, payload, signature] = decoder
+    .decode(attestation);
+  const fields = decoder.decode(payload);
+  const edited = edit(fields) ?? encode(fields);
+  return encode([protectedHeaders, unprotectedHeaders, edited, signature]);
+}
+
+/**
+ * Re-sign an attestation with one of the alternative test PKIs in
+ * `test_data/<pki>/`, replacing the document's certificate chain with its own
+ */
+async function signedBy(
+  attestation: Uint8Array,
+  pki: string,
+): Promise<Uint8Array> {
+  const file = (name: string) => Deno.readFileSync(`./test_data/${pki}/${name}`);
+  // The signature covers exact encodings, so keep byte strings untagged
+  const encoder = new Encoder({ tagUint8Array: false });
+  const decoder = new Decoder({ mapsAsObjects: false });
+  const [protectedHeaders, unprotectedHeaders, payload] = decoder.decode(
+    attestation,
+  );
+  const fields = decoder.decode(payload);
+  fields.set("certificate", file("end-certificate.der"));
+  fields.set("cabundle", [
+    file("root-certificate.der"),
+    file("int-certificate.der"),
+  ]);
+  const signedPayload = encoder.encode(fields);
+
+  const key = await crypto.subtle.importKey(
+    "pkcs8",
+    file("end-signing-key.der"),
+    { name: "ECDSA", namedCurve: "P-384" },
+    false,
+    ["sign"],
+  );
+  // COSE Sig_structure (RFC 9052 section 4.4), with no external data
+  const toBeSigned = encoder.encode([
+    "Signature1",
+    protectedHeaders,
+    new Uint8Array(),
+    signedPayload,
+  ]);
+  const signature = await crypto.subtle.sign(
+    { name: "ECDSA", hash: "SHA-384" },
+    key,
+    toBeSigned,
+  );
+  return encoder.encode([
+    protectedHeaders,
+    unprotectedHeaders,
+    signedPayload,
+    new Uint8Array(signature),
+  ]);
+}
 
 Deno.test(function attestBasicTest() {
   const userData = { message: "test data", timestamp: Date.now() };
@@ -54,8 +220,2112 @@ const quote = Buffer.from(
   "base64",
 );
 
-// TODO: Add a real test once mock attestation is implemented
 Deno.test(function attestTest() {
-  const output = attest(quote);
-  assertEquals(output, Uint8Array.from(""));
+  // The NSM refuses user data this large (a whole attestation), but the
+  // development driver does not limit it
+  if (dev) {
+    assertEquals(attest(quote).length > 0, true);
+    return;
+  }
+  assertEquals(
+    assertThrows(() => attest(quote), NitroError).code,
+    ErrorCode.ATTESTATION_FAILED,
+  );
+});
+
+const awsRoot = await Deno.readFile("./certs/aws-nitro-enclaves-root-g1.der");
+
+Deno.test(function verifyAttestationTest() {
+  assertEquals(verifyAttestation(quote, awsRoot), ErrorCode.OK);
+
+  const devRoot = Deno.readFileSync("./test_data/root-certificate.der");
+  assertEquals(verifyAttestation(quote, devRoot), ErrorCode.CHAIN_INVALID);
+
+  const tampered = Uint8Array.from(quote);
+  tampered[tampered.length - 1] ^= 1;
+  assertEquals(
+    verifyAttestation(tampered, awsRoot),
+    ErrorCode.SIGNATURE_INVALID,
+  );
+});
+
+Deno.test(async function verifyAttestationEnvRootTest() {
+  // The default root is resolved once per process, so check it in a fresh one
+  const output = await inDevProcess(
+    `import { Buffer } from "node:buffer";
+    import { verifyAttestation } from "./mod.ts";
+    const quote = Buffer.from(
+      Deno.readTextFileSync("./test-quote.b64"),
+      "base64",
+    );
+    const vector = Deno.readFileSync("./test_data/test-vector.cbor");
+    console.log(JSON.stringify([
+      verifyAttestation(vector),
+      verifyAttestation(quote),
+    ]));`,
+    [],
+    { DENO_NITRO_ROOT_CERT: "./test_data/root-certificate.der" },
+  );
+  // The development root replaces the AWS one as the default
+  assertEquals(JSON.parse(output), [ErrorCode.OK, ErrorCode.CHAIN_INVALID]);
+});
+
+Deno.test(function attestBatchTooLargeTest() {
+  setBatchLimits(2, 1024);
+  try {
+    const err = assertThrows(
+      () => attestBatch(["one", "two", "three"]),
+      NitroError,
+    );
+    assertEquals(err.code, ErrorCode.BATCH_TOO_LARGE);
+
+    const big = new Uint8Array(2048);
+    assertEquals(
+      assertThrows(() => attestBatch([big]), NitroError).code,
+      ErrorCode.BATCH_TOO_LARGE,
+    );
+  } finally {
+    setBatchLimits(1024, 16 * 1024 * 1024);
+  }
+});
+
+Deno.test({
+  name: "signingAlgorithmTest",
+  ignore: !dev,
+  fn() {
+    // test_data/end-signing-key.der is a P-384 key
+    const { attestations } = stats();
+    assertEquals(signingAlgorithm(), CoseAlgorithm.ES384);
+    // Its probe attestation is not one of the caller's
+    assertEquals(stats().attestations, attestations);
+  },
+});
+
+Deno.test({
+  name: "documentIsFreshTest",
+  ignore: !dev,
+  fn() {
+    const fresh = attest("fresh");
+    assertEquals(documentIsFresh(fresh, 60_000), true);
+
+    const aged = withPayload(fresh, (payload) => {
+      payload.set("timestamp", Date.now() - 3_600_000);
+    });
+    assertEquals(documentIsFresh(aged, 60_000), false);
+    assertEquals(documentIsFresh(aged, 2 * 3_600_000), true);
+
+    assertEquals(documentIsFresh(new Uint8Array([1, 2, 3]), 60_000), false);
+  },
+});
+
+Deno.test(function configureTest() {
+  const previous = currentOptions();
+  try {
+    configure({ batchMaxItems: 2, batchMaxBytes: 1024, logLevel: "error" });
+    assertEquals(currentOptions(), {
+      batchMaxItems: 2,
+      batchMaxBytes: 1024,
+      logLevel: "error",
+      attestPoolSize: previous.attestPoolSize,
+      verificationCacheTtlMs: previous.verificationCacheTtlMs,
+      verificationCacheSize: previous.verificationCacheSize,
+      strictCertValidity: previous.strictCertValidity,
+      certClockSkewMs: previous.certClockSkewMs,
+      embedBootSessionId: previous.embedBootSessionId,
+      rateLimitCapacity: previous.rateLimitCapacity,
+      rateLimitRefillPerSecond: previous.rateLimitRefillPerSecond,
+      relaxPcrChecks: previous.relaxPcrChecks,
+      documentCacheSize: previous.documentCacheSize,
+      maxInputBytes: previous.maxInputBytes,
+      noncePoolSize: previous.noncePoolSize,
+      attestQuotaBytes: previous.attestQuotaBytes,
+      attestQuotaWindowMs: previous.attestQuotaWindowMs,
+      seenNonceCapacity: previous.seenNonceCapacity,
+      seenNonceTtlMs: previous.seenNonceTtlMs,
+    });
+    assertEquals(
+      assertThrows(() => attestBatch(["one", "two", "three"]), NitroError)
+        .code,
+      ErrorCode.BATCH_TOO_LARGE,
+    );
+
+    // Rejected as a whole, naming the invalid option
+    const err = assertThrows(
+      () => configure({ batchMaxItems: 8, batchMaxBytes: 0 }),
+      NitroError,
+    );
+    assertEquals(err.code, ErrorCode.INVALID_ARGUMENT);
+    assertEquals(err.message.includes("batchMaxBytes"), true);
+    assertEquals(currentOptions().batchMaxItems, 2);
+
+    assertThrows(
+      () => configure({ logLevel: "loud" }),
+      NitroError,
+      "logLevel",
+    );
+    assertThrows(
+      () => configure({ unknown: true } as InitOptions),
+      NitroError,
+      "unknown",
+    );
+
+    initDriver();
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test(function verifyAttestationMalformedPayloadTest() {
+  const missing = withPayload(quote, (payload) => {
+    payload.delete("timestamp");
+  });
+  assertEquals(
+    verifyAttestation(missing, awsRoot),
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+  assertEquals(lastErrorMessage(), "malformed document: missing timestamp");
+
+  // cbor-x cannot encode a map with a repeated key, so assemble one by hand
+  const duplicated = withPayload(quote, (payload) => {
+    const entries = [...payload, ["timestamp", 0]];
+    return Uint8Array.from([
+      0xa0 + entries.length,
+      ...entries.flatMap(([key, value]) => [...encode(key), ...encode(value)]),
+    ]);
+  });
+  assertEquals(
+    verifyAttestation(duplicated, awsRoot),
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+  assertEquals(
+    lastErrorMessage(),
+    "malformed document: duplicate field in payload",
+  );
+});
+
+Deno.test(async function compressedAttestationTest() {
+  const compressed = attestCompressed({ message: "compress me" });
+  const attestation = decompressDocument(compressed);
+  assertEquals(attestation.length > compressed.length, true);
+  assertEquals(validate(attestation).payload.user_data, {
+    message: "compress me",
+  });
+
+  // Any raw DEFLATE implementation can decompress, to the identical bytes
+  const inflated = new Uint8Array(
+    await new Response(
+      new Blob([compressed]).stream().pipeThrough(
+        new DecompressionStream("deflate-raw"),
+      ),
+    ).arrayBuffer(),
+  );
+  assertEquals(inflated, attestation);
+
+  assertEquals(
+    assertThrows(() => decompressDocument(quote), NitroError).code,
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+});
+
+Deno.test(function framedAttestationTest() {
+  const plain = attestFramed({ message: "frame me" });
+  const attestation = deframe(plain);
+  assertEquals(plain.length, attestation.length + 4);
+  assertEquals(validate(attestation).payload.user_data, {
+    message: "frame me",
+  });
+
+  const framed = attestFramed({ message: "frame me" }, { checksum: true });
+  assertEquals(validate(deframe(framed)).payload.user_data, {
+    message: "frame me",
+  });
+
+  // Any flipped byte, in the length, attestation or trailer, is caught
+  for (const index of [3, 100, framed.length - 1]) {
+    const corrupt = framed.slice();
+    corrupt[index] ^= 1;
+    assertEquals(
+      assertThrows(() => deframe(corrupt), NitroError).code,
+      ErrorCode.FRAME_CORRUPT,
+    );
+  }
+  for (const truncated of [framed.subarray(0, 2), framed.subarray(0, -1)]) {
+    assertEquals(
+      assertThrows(() => deframe(truncated), NitroError).code,
+      ErrorCode.FRAME_CORRUPT,
+    );
+  }
+});
+
+Deno.test(function chainBrokenTest() {
+  // Drop an intermediate from the middle of the AWS quote's cabundle
+  const gap = withPayload(quote, (payload) => {
+    const cabundle = payload.get("cabundle") as Uint8Array[];
+    payload.set("cabundle", cabundle.toSpliced(2, 1));
+  });
+  assertEquals(verifyAttestation(gap, awsRoot), ErrorCode.CHAIN_BROKEN);
+  assertEquals(
+    lastErrorMessage(),
+    "certificate chain does not link up to the leaf",
+  );
+});
+
+Deno.test({
+  name: "chainBrokenDevTest",
+  ignore: !dev,
+  fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const attestation = attest("gap");
+    // The intermediate is issued by the root, but is not its own issuer
+    const gap = withPayload(attestation, (payload) => {
+      const [intermediate] = payload.get("cabundle") as Uint8Array[];
+      payload.set("cabundle", [intermediate, intermediate]);
+    });
+    assertEquals(verifyAttestation(gap, root), ErrorCode.CHAIN_BROKEN);
+    // Whereas a chain from another root is not trusted at all
+    assertEquals(
+      verifyAttestation(attestation, awsRoot),
+      ErrorCode.CHAIN_INVALID,
+    );
+  },
+});
+
+/** PEM bundle of DER certificates, as operators keep their roots */
+function pemBundle(...certificates: Uint8Array[]): Uint8Array {
+  const pem = certificates.map((der) => {
+    const base64 = btoa(String.fromCharCode(...der)).replace(/.{64}/g, "$&\n");
+    return `-----BEGIN CERTIFICATE-----\n${base64.trimEnd()}\n` +
+      "-----END CERTIFICATE-----\n";
+  });
+  return new TextEncoder().encode(pem.join(""));
+}
+
+Deno.test({
+  name: "pemRootBundleTest",
+  ignore: !dev,
+  fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const otherRoot = Deno.readFileSync(
+      "./test_data/expired/root-certificate.der",
+    );
+    const attestation = attest("bundled");
+
+    // Issued by the second root of the bundle
+    const bundle = pemBundle(otherRoot, root);
+    assertEquals(verifyAttestation(attestation, bundle), ErrorCode.OK);
+    assertEquals(verifyWithPolicy(attestation, { root: bundle }), ErrorCode.OK);
+    assertEquals(
+      verifyAttestation(attestation, pemBundle(otherRoot)),
+      ErrorCode.CHAIN_INVALID,
+    );
+
+    // Every entry must be a certificate
+    assertEquals(
+      verifyAttestation(attestation, pemBundle(root, new Uint8Array(8))),
+      ErrorCode.INVALID_ROOT,
+    );
+  },
+});
+
+Deno.test(function nonUtf8Test() {
+  // Binary user data is carried as a byte string, and comes back intact
+  const binary = new Uint8Array([0xff, 0xfe, 0xc3, 0x28]);
+  const attestation = attest(binary);
+  assertEquals(validate(attestation).payload.user_data, binary);
+  assertEquals(documentFingerprint(attestation).length, 32);
+
+  // A text field that is not UTF-8 is rejected, with a readable reason
+  const invalidText = withPayload(quote, (payload) =>
+    Uint8Array.from([
+      0xa0 + payload.size,
+      ...[...payload].flatMap(([key, value]) => [
+        ...encode(key),
+        ...(key === "module_id"
+          ? [0x64, 0xff, 0xfe, 0x74, 0x74]
+          : encode(value)),
+      ]),
+    ])
+  );
+  assertEquals(
+    verifyAttestation(invalidText, awsRoot),
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+  assertEquals(
+    lastErrorMessage(),
+    "malformed document: invalid UTF-8 in payload text",
+  );
+  assertEquals(
+    assertThrows(() => documentTimestamp(invalidText), NitroError).code,
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+});
+
+Deno.test({
+  name: "documentTimestampTest",
+  ignore: !dev,
+  fn() {
+    const before = Date.now();
+    const timestamp = documentTimestamp(attest("timestamp"));
+    assertEquals(timestamp >= before - 1000, true);
+    assertEquals(timestamp <= Date.now() + 1000, true);
+
+    assertEquals(
+      assertThrows(() => documentTimestamp(new Uint8Array([1])), NitroError)
+        .code,
+      ErrorCode.MALFORMED_DOCUMENT,
+    );
+  },
+});
+
+Deno.test({
+  name: "documentChainLenTest",
+  ignore: !dev,
+  async fn() {
+    // The NSM's leaf and four CA certificates
+    assertEquals(documentChainLen(quote), 5);
+    // The development leaf and its intermediate
+    assertEquals(documentChainLen(attest("depth")), 2);
+
+    // Two intermediates: the default one and the root above it
+    const output = await inDevProcess(
+      `import { attest, documentChainLen } from "./mod.ts";
+      console.log(documentChainLen(attest("depth")));`,
+      [
+        "--int-certs",
+        "./test_data/int-certificate.der",
+        "--int-certs",
+        "./test_data/root-certificate.der",
+      ],
+    );
+    assertEquals(output, "3");
+
+    assertEquals(
+      assertThrows(() => documentChainLen(new Uint8Array([1])), NitroError)
+        .code,
+      ErrorCode.MALFORMED_DOCUMENT,
+    );
+  },
+});
+
+Deno.test({
+  name: "requestIdLoggingTest",
+  ignore: !dev,
+  async fn() {
+    const { stderr } = await new Deno.Command(Deno.execPath(), {
+      args: [
+        "eval",
+        `import { attest, attestAsync } from "./mod.ts";
+        attest("traced", { requestId: "req-42" });
+        attest("untraced");
+        // Attested on a pool thread, which carries the ID over
+        await attestAsync("traced", { requestId: "req-43" });
+        await attestAsync("untraced");`,
+      ],
+      env: { DENO_NITRO_LOG: "debug" },
+    }).output();
+    const attested = new TextDecoder().decode(stderr).split("\n")
+      .filter((line) => line.includes("attested in"));
+    assertEquals(attested.length, 4);
+    assertEquals(attested[0].startsWith("[deno_nitro DEBUG req-42] "), true);
+    assertEquals(attested[1].startsWith("[deno_nitro DEBUG] "), true);
+    assertEquals(attested[2].startsWith("[deno_nitro DEBUG req-43] "), true);
+    assertEquals(attested[3].startsWith("[deno_nitro DEBUG] "), true);
+  },
+});
+
+Deno.test({
+  name: "nonzeroPcrsTest",
+  ignore: !dev,
+  fn() {
+    // The development driver reports every PCR as zeros, like a debug enclave
+    const debug = attest("pcrs");
+    assertEquals(nonzeroPcrs(debug), 0);
+
+    const measured = withPayload(debug, (payload) => {
+      const pcrs = payload.get("pcrs") as Map<number, Uint8Array>;
+      pcrs.set(0, new Uint8Array(pcrs.get(0)!.length).fill(0xab));
+    });
+    assertEquals(nonzeroPcrs(measured), 0b1);
+
+    assertEquals(
+      assertThrows(() => nonzeroPcrs(new Uint8Array([1])), NitroError).code,
+      ErrorCode.MALFORMED_DOCUMENT,
+    );
+  },
+});
+
+Deno.test({
+  name: "documentPcrTest",
+  ignore: !dev,
+  fn() {
+    // The development driver reports every PCR as SHA-384 zeros
+    const attestation = attest("pcr0");
+    assertEquals(documentPcr(attestation, 0), new Uint8Array(48));
+    // It only reports the PCRs an enclave would have measured
+    assertEquals(documentPcr(attestation, 31), new Uint8Array());
+
+    const measured = withPayload(attestation, (payload) => {
+      const pcrs = payload.get("pcrs") as Map<number, Uint8Array>;
+      pcrs.set(0, new Uint8Array(48).fill(0xab));
+      pcrs.delete(8);
+    });
+    assertEquals(documentPcr(measured, 0), new Uint8Array(48).fill(0xab));
+    assertEquals(documentPcr(measured, 8), new Uint8Array());
+
+    assertEquals(
+      assertThrows(() => documentPcr(attestation, 32), NitroError).code,
+      ErrorCode.INVALID_ARGUMENT,
+    );
+  },
+});
+
+Deno.test({
+  name: "validatePcrBanksTest",
+  ignore: !dev,
+  fn() {
+    const attestation = attest("pcr banks");
+    assertEquals(validatePcrBanks(attestation), ErrorCode.OK);
+
+    const previous = currentOptions();
+    try {
+      // Checked even where relaxed decoding would accept the document
+      configure({ relaxPcrChecks: true });
+      const short = withPayload(attestation, (payload) => {
+        const pcrs = payload.get("pcrs") as Map<number, Uint8Array>;
+        pcrs.set(1, new Uint8Array(32));
+      });
+      assertEquals(validatePcrBanks(short), ErrorCode.PCR_LENGTH_MISMATCH);
+      assertEquals(
+        lastErrorMessage(),
+        "malformed document: PCR length does not match the digest",
+      );
+
+      const outOfRange = withPayload(attestation, (payload) => {
+        const pcrs = payload.get("pcrs") as Map<number, Uint8Array>;
+        pcrs.set(40, new Uint8Array(48));
+      });
+      assertEquals(
+        validatePcrBanks(outOfRange),
+        ErrorCode.PCR_INDEX_OUT_OF_RANGE,
+      );
+      assertEquals(
+        lastErrorMessage(),
+        "malformed document: PCR index out of range",
+      );
+
+      const empty = withPayload(attestation, (payload) => {
+        payload.set("pcrs", new Map());
+      });
+      assertEquals(validatePcrBanks(empty), ErrorCode.PCR_COUNT_INVALID);
+      assertEquals(
+        lastErrorMessage(),
+        "malformed document: unexpected number of PCRs",
+      );
+
+      const duplicate = withPayload(attestation, (payload) => {
+        const pcr = new Uint8Array(48).fill(7);
+        payload.set("pcrs", new Map([[0, pcr], [1, pcr]]));
+        // Renumber PCR 1 as a second PCR 0, which a Map cannot hold
+        const bytes = encode(payload).slice();
+        const entry = [1, ...encode(pcr)];
+        const at = bytes.findIndex((_, i) =>
+          entry.every((byte, j) => bytes[i + j] === byte)
+        );
+        bytes[at] = 0;
+        return bytes;
+      });
+      assertEquals(validatePcrBanks(duplicate), ErrorCode.PCR_INDEX_DUPLICATE);
+      assertEquals(
+        lastErrorMessage(),
+        "malformed document: duplicate PCR index",
+      );
+
+      // Other functions report every anomaly as a malformed document
+      configure({ relaxPcrChecks: false });
+      assertEquals(
+        verifyAttestation(short, awsRoot),
+        ErrorCode.MALFORMED_DOCUMENT,
+      );
+      assertEquals(
+        verifyAttestation(duplicate, awsRoot),
+        ErrorCode.MALFORMED_DOCUMENT,
+      );
+    } finally {
+      configure(previous);
+    }
+  },
+});
+
+Deno.test({
+  name: "verifyAgainstSnapshotTest",
+  ignore: !dev,
+  fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const attestation = attest("snapshot");
+    const { module_id, pcrs } = validate(attestation).payload as {
+      module_id: string;
+      pcrs: Record<number, Uint8Array>;
+    };
+
+    const spec = {
+      root,
+      pcrs: { 0: [pcrs[0]] },
+      moduleIds: [module_id],
+      maxAgeMs: 60_000,
+    };
+    const snapshot = buildSnapshot(spec);
+    assertEquals(buildSnapshot(spec), snapshot);
+    assertEquals(verifyAgainstSnapshot(attestation, snapshot), ErrorCode.OK);
+
+    const otherPcr = buildSnapshot({
+      root,
+      pcrs: { 0: [new Uint8Array(pcrs[0].length).fill(1)] },
+    });
+    assertEquals(
+      verifyAgainstSnapshot(attestation, otherPcr),
+      ErrorCode.POLICY_VIOLATION,
+    );
+
+    const otherModule = buildSnapshot({ root, moduleIds: ["i-other"] });
+    assertEquals(
+      verifyAgainstSnapshot(attestation, otherModule),
+      ErrorCode.POLICY_VIOLATION,
+    );
+
+    // The AWS attestation does not chain to the development root
+    assertEquals(
+      verifyAgainstSnapshot(quote, buildSnapshot({ root })),
+      ErrorCode.CHAIN_INVALID,
+    );
+
+    const invalid = { root: new Uint8Array([1]) };
+    assertEquals(
+      assertThrows(() => buildSnapshot(invalid), NitroError).code,
+      ErrorCode.INVALID_ROOT,
+    );
+  },
+});
+
+Deno.test(function verifyWithPolicyCborTest() {
+  const policy = { root: awsRoot, requiredDigest: DigestAlgorithm.SHA384 };
+  const { code, message, document } = verifyWithPolicyCbor(quote, policy);
+  assertEquals(code, verifyWithPolicy(quote, policy));
+  assertEquals(code, ErrorCode.OK);
+  assertEquals(message, "");
+
+  // The same fields as the decoded attestation, still as bytes
+  const payload = validate(quote).payload as {
+    module_id: string;
+    timestamp: number;
+    pcrs: Record<number, Uint8Array>;
+    public_key: Uint8Array | null;
+  };
+  assertEquals(document?.moduleId, payload.module_id);
+  assertEquals(document?.timestamp, payload.timestamp);
+  assertEquals(document?.digest, DigestAlgorithm.SHA384);
+  assertEquals(Object.keys(document?.pcrs ?? {}), Object.keys(payload.pcrs));
+  for (const [index, value] of Object.entries(payload.pcrs)) {
+    assertEquals(
+      new Uint8Array(document?.pcrs[Number(index)] ?? []),
+      new Uint8Array(value),
+    );
+  }
+  assertEquals(document?.publicKey, payload.public_key);
+
+  // Rejections carry the reason, and no payload
+  const stale = { ...policy, maxAgeMs: 1 };
+  const rejected = verifyWithPolicyCbor(quote, stale);
+  assertEquals(rejected.code, verifyWithPolicy(quote, stale));
+  assertEquals(rejected.code, ErrorCode.POLICY_VIOLATION);
+  assertEquals(rejected.message, lastErrorMessage());
+  assertEquals(rejected.document, null);
+});
+
+Deno.test({
+  name: "explainVerificationTest",
+  ignore: !dev,
+  fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const attestation = attest("explain");
+    const pcr0 = new Uint8Array(48).fill(0xab);
+    const policy = { root, pcrs: { 0: [pcr0] }, maxAgeMs: 60_000 };
+    assertEquals(
+      explainVerification(attestation, policy),
+      [
+        "attestation rejected: policy violation: PCR value not accepted",
+        "chain and signature: passed",
+        "module ID: not checked",
+        "age: passed",
+        "PCR0: failed",
+        `  expected: ${"ab".repeat(48)}`,
+        `  actual:   ${"00".repeat(48)}`,
+        "nonce: not checked",
+        "debug mode: not checked",
+        "digest: not checked",
+        "leaf key: not checked",
+      ].join("\n"),
+    );
+    // The outcome is the one verifyWithPolicy reports
+    const message = lastErrorMessage();
+    assertEquals(
+      verifyWithPolicy(attestation, policy),
+      ErrorCode.POLICY_VIOLATION,
+    );
+    assertEquals(lastErrorMessage(), message);
+  },
+});
+
+Deno.test({
+  name: "verifyBatchTest",
+  ignore: !dev,
+  fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const valid = attest("in a batch");
+    const tampered = attest("tampered").slice();
+    tampered[tampered.length - 1] ^= 1;
+    const attestations = [valid, new Uint8Array([1, 2, 3]), tampered, valid];
+    assertEquals(verifyBatch(attestations, { root }), [
+      ErrorCode.OK,
+      ErrorCode.MALFORMED_DOCUMENT,
+      ErrorCode.SIGNATURE_INVALID,
+      ErrorCode.OK,
+    ]);
+    // The chain and signature are checked before the policy's constraints
+    assertEquals(verifyBatch(attestations, { root, moduleIds: ["i-other"] }), [
+      ErrorCode.POLICY_VIOLATION,
+      ErrorCode.MALFORMED_DOCUMENT,
+      ErrorCode.SIGNATURE_INVALID,
+      ErrorCode.POLICY_VIOLATION,
+    ]);
+    assertEquals(verifyBatch([], { root }), []);
+
+    assertEquals(
+      assertThrows(
+        () => verifyBatch([valid], { root: new Uint8Array([1]) }),
+        NitroError,
+      ).code,
+      ErrorCode.INVALID_ROOT,
+    );
+
+    const previous = currentOptions();
+    try {
+      // Each document is held to maxInputBytes, the batch to batchMaxBytes
+      configure({ maxInputBytes: valid.length });
+      const oversized = new Uint8Array(valid.length + 1);
+      oversized.set(valid);
+      assertEquals(verifyBatch([valid, oversized, valid], { root }), [
+        ErrorCode.OK,
+        ErrorCode.INPUT_TOO_LARGE,
+        ErrorCode.OK,
+      ]);
+      configure({ batchMaxBytes: 2 * valid.length - 1 });
+      assertEquals(
+        assertThrows(() => verifyBatch([valid, valid], { root }), NitroError)
+          .code,
+        ErrorCode.BATCH_TOO_LARGE,
+      );
+    } finally {
+      configure(previous);
+    }
+  },
+});
+
+Deno.test({
+  name: "verifyWithPolicyTest",
+  ignore: !dev,
+  fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const challenge = crypto.getRandomValues(new Uint8Array(32));
+    const { attestation } = attestAndSign(challenge);
+    const { module_id, pcrs } = validate(attestation).payload as {
+      module_id: string;
+      pcrs: Record<number, Uint8Array>;
+    };
+    const policy = {
+      root,
+      pcrs: { 0: [pcrs[0]] },
+      moduleIds: [module_id],
+      nonce: challenge,
+      maxAgeMs: 60_000,
+      requiredDigest: DigestAlgorithm.SHA384,
+    };
+    assertEquals(verifyWithPolicy(attestation, policy), ErrorCode.OK);
+
+    // Each change breaks exactly one constraint of an otherwise passing policy
+    const otherNonce = new Uint8Array(32);
+    const broken: [VerificationPolicy, string][] = [
+      [{ nonce: otherNonce }, "nonce"],
+      [{ moduleIds: ["i-other"] }, "module_id"],
+      [{ pcrs: { 0: [new Uint8Array(pcrs[0].length).fill(1)] } }, "PCR"],
+      [{ requiredDigest: DigestAlgorithm.SHA512 }, "digest"],
+      // The development driver reports every PCR as zeros, like a debug enclave
+      [{ rejectDebug: true }, "debug"],
+    ];
+    for (const [change, reason] of broken) {
+      assertEquals(
+        verifyWithPolicy(attestation, { ...policy, ...change }),
+        ErrorCode.POLICY_VIOLATION,
+      );
+      assertEquals(lastErrorMessage().includes(reason), true);
+    }
+
+    // A document failing verification is rejected before any constraint
+    assertEquals(
+      verifyWithPolicy(quote, { ...policy, nonce: otherNonce }),
+      ErrorCode.CHAIN_INVALID,
+    );
+    assertEquals(
+      verifyWithPolicy(attestation, { ...policy, requiredDigest: 1 }),
+      ErrorCode.INVALID_ARGUMENT,
+    );
+  },
+});
+
+/** DER SubjectPublicKeyInfo of a development PKI's end certificate */
+async function endCertificateKey(pki = "."): Promise<Uint8Array> {
+  const der = Deno.readFileSync(`./test_data/${pki}/end-signing-key.der`);
+  const ecdsa = { name: "ECDSA", namedCurve: "P-384" };
+  const key = await crypto.subtle.importKey("pkcs8", der, ecdsa, true, [
+    "sign",
+  ]);
+  // Drop the private part to get at the public key
+  const { d: _, ...jwk } = await crypto.subtle.exportKey("jwk", key);
+  const publicKey = await crypto.subtle.importKey("jwk", jwk, ecdsa, true, [
+    "verify",
+  ]);
+  return new Uint8Array(await crypto.subtle.exportKey("spki", publicKey));
+}
+
+Deno.test({
+  name: "leafKeysTest",
+  ignore: !dev,
+  async fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const attestation = attest({ pinned: true });
+    const leafKey = await endCertificateKey();
+    const otherKey = await endCertificateKey("expired");
+
+    assertEquals(
+      verifyWithPolicy(attestation, { root, leafKeys: [leafKey] }),
+      ErrorCode.OK,
+    );
+    assertEquals(
+      verifyWithPolicy(attestation, { root, leafKeys: [otherKey, leafKey] }),
+      ErrorCode.OK,
+    );
+
+    // The root still vouches for the leaf, but its key is not pinned
+    assertEquals(
+      verifyWithPolicy(attestation, { root, leafKeys: [otherKey] }),
+      ErrorCode.POLICY_VIOLATION,
+    );
+    assertEquals(lastErrorMessage().includes("leaf public key"), true);
+
+    assertEquals(
+      verifyWithPolicy(attestation, { root, leafKeys: [new Uint8Array(8)] }),
+      ErrorCode.INVALID_ARGUMENT,
+    );
+  },
+});
+
+Deno.test(function statsTest() {
+  const before = stats();
+  for (let i = 0; i < 20; i++) {
+    attest({ i });
+  }
+  const after = stats();
+
+  assertEquals(after.attestations - before.attestations, 20);
+  const { p50, p95, p99, max } = after.latencyMicros;
+  assertEquals(p50 <= p95 && p95 <= p99 && p99 <= max, true);
+});
+
+Deno.test(function structRoundTripTest() {
+  // Options read back can be written again unchanged
+  const options = currentOptions();
+  configure(options);
+  assertEquals(currentOptions(), options);
+
+  // Structs are CBOR maps with the documented keys on every platform
+  const { latencyMicros, ...counts } = stats();
+  assertEquals(Object.keys(counts), [
+    "attestations",
+    "failures",
+    "peakAsyncAttestations",
+    "verificationCacheHits",
+    "documentCacheHits",
+    "driverInitMicros",
+    "pooledNonces",
+  ]);
+  assertEquals(Object.keys(latencyMicros), ["p50", "p95", "p99", "max"]);
+
+  // A snapshot decodes back into a spec that rebuilds it byte for byte
+  const snapshot = buildSnapshot({
+    root: awsRoot,
+    pcrs: { 3: [new Uint8Array(48)] },
+    moduleIds: ["i-0123456789abcdef0"],
+    maxAgeMs: 300_000,
+  });
+  assertEquals(buildSnapshot(decode(snapshot)), snapshot);
+});
+
+Deno.test(async function verifyBoundPublicKeyTest() {
+  const spki = async () => {
+    const { publicKey } = await crypto.subtle.generateKey(
+      { name: "ECDSA", namedCurve: "P-384" },
+      true,
+      ["sign", "verify"],
+    );
+    return new Uint8Array(await crypto.subtle.exportKey("spki", publicKey));
+  };
+  const expected = await spki();
+
+  // The AWS attestation does not embed a key
+  assertEquals(verifyBoundPublicKey(quote, expected), false);
+
+  const bound = withPayload(quote, (payload) => {
+    payload.set("public_key", expected);
+  });
+  assertEquals(verifyBoundPublicKey(bound, expected), true);
+  assertEquals(verifyBoundPublicKey(bound, await spki()), false);
+
+  assertEquals(
+    assertThrows(
+      () => verifyBoundPublicKey(bound, new Uint8Array([1, 2])),
+      NitroError,
+    ).code,
+    ErrorCode.INVALID_ARGUMENT,
+  );
+});
+
+Deno.test({
+  name: "attestTlsCertTest",
+  ignore: !dev,
+  fn() {
+    // Any certificate will do as the server's
+    const cert = Deno.readFileSync("./test_data/root-certificate.der");
+    const nonce = new Uint8Array(16).fill(7);
+    const attestation = attestTlsCert(cert, nonce);
+    assertEquals(verifyAttestation(attestation, cert), ErrorCode.OK);
+    assertEquals(verifyTlsCertBinding(attestation, cert), true);
+
+    const other = cert.slice();
+    other[other.length - 1] ^= 1;
+    assertEquals(verifyTlsCertBinding(attestation, other), false);
+    // The nonce is optional
+    assertEquals(verifyTlsCertBinding(attestTlsCert(cert), cert), true);
+
+    assertEquals(
+      assertThrows(
+        () => attestTlsCert(new Uint8Array([1, 2, 3])),
+        NitroError,
+      ).code,
+      ErrorCode.INVALID_ARGUMENT,
+    );
+    assertEquals(
+      assertThrows(
+        () => verifyTlsCertBinding(new Uint8Array([1]), cert),
+        NitroError,
+      ).code,
+      ErrorCode.MALFORMED_DOCUMENT,
+    );
+  },
+});
+
+Deno.test({
+  name: "attestHashedTest",
+  ignore: !dev,
+  fn() {
+    // Far more than the NSM takes as user_data
+    const payload = new Uint8Array(1024 * 1024).map((_, i) => i % 251);
+    const attestation = attestHashed(payload);
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    assertEquals(verifyAttestation(attestation, root), ErrorCode.OK);
+    assertEquals(verifyUserDataHash(attestation, payload), true);
+
+    const tampered = payload.slice();
+    tampered[tampered.length - 1] ^= 1;
+    assertEquals(verifyUserDataHash(attestation, tampered), false);
+    // Plain attestations carry the payload, not its hash
+    const small = payload.slice(0, 64);
+    assertEquals(verifyUserDataHash(attest(small), small), false);
+
+    // The boot session header is looked through
+    const previous = currentOptions();
+    try {
+      configure({ embedBootSessionId: true });
+      assertEquals(verifyUserDataHash(attestHashed(payload), payload), true);
+    } finally {
+      configure(previous);
+    }
+
+    assertEquals(
+      assertThrows(
+        () => verifyUserDataHash(new Uint8Array([1]), payload),
+        NitroError,
+      ).code,
+      ErrorCode.MALFORMED_DOCUMENT,
+    );
+  },
+});
+
+Deno.test(async function documentKeyCommitmentTest() {
+  const spki = async () => {
+    const { publicKey } = await crypto.subtle.generateKey(
+      { name: "ECDSA", namedCurve: "P-384" },
+      true,
+      ["sign", "verify"],
+    );
+    return new Uint8Array(await crypto.subtle.exportKey("spki", publicKey));
+  };
+  const bind = (document: Uint8Array, key: Uint8Array) =>
+    withPayload(document, (payload) => {
+      payload.set("public_key", key);
+    });
+
+  // The AWS attestation does not embed a key
+  assertEquals(documentKeyCommitment(quote), new Uint8Array(32));
+  assertEquals(
+    documentKeyCommitment(bind(quote, new Uint8Array())),
+    new Uint8Array(32),
+  );
+
+  const key = await spki();
+  const commitment = documentKeyCommitment(bind(quote, key));
+  assertEquals(
+    commitment,
+    new Uint8Array(await crypto.subtle.digest("SHA-256", key)),
+  );
+  // Stable across documents binding the same key
+  const other = withPayload(bind(quote, key), (payload) => {
+    payload.set("user_data", new Uint8Array([1]));
+  });
+  assertEquals(documentKeyCommitment(other), commitment);
+  assertNotEquals(
+    documentKeyCommitment(bind(quote, await spki())),
+    commitment,
+  );
+
+  assertEquals(
+    assertThrows(() => documentKeyCommitment(new Uint8Array([1])), NitroError)
+      .code,
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+});
+
+Deno.test(async function documentFingerprintTest() {
+  const fingerprint = documentFingerprint(quote);
+  assertEquals(fingerprint.length, 32);
+  assertEquals(documentFingerprint(Uint8Array.from(quote)), fingerprint);
+
+  // Reproducible with any SHA-256 implementation
+  assertEquals(
+    fingerprint,
+    new Uint8Array(await crypto.subtle.digest("SHA-256", quote)),
+  );
+
+  const flipped = Uint8Array.from(quote);
+  flipped[flipped.length - 1] ^= 1;
+  assertNotEquals(documentFingerprint(flipped), fingerprint);
+
+  assertEquals(
+    assertThrows(() => documentFingerprint(new Uint8Array([1])), NitroError)
+      .code,
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+});
+
+Deno.test({
+  name: "noncePoolTest",
+  ignore: !dev,
+  async fn() {
+    const waitForPooled = async (count: number) => {
+      for (let i = 0; i < 100 && stats().pooledNonces !== count; i++) {
+        await new Promise((resolve) => setTimeout(resolve, 10));
+      }
+      assertEquals(stats().pooledNonces, count);
+    };
+    const seen = new Set<string>();
+    const takeNonce = () => {
+      const { payload } = validate(attestWithFreshNonce("nonce"));
+      const nonce = new Uint8Array(payload.nonce as Uint8Array);
+      assertEquals(nonce.length, 32);
+      seen.add(nonce.join());
+    };
+
+    const previous = currentOptions();
+    try {
+      // Filled in the background
+      configure({ noncePoolSize: 8 });
+      await waitForPooled(8);
+
+      // Topped up again once fewer than half remain
+      for (let i = 0; i < 5; i++) takeNonce();
+      await waitForPooled(8);
+      // Drained past empty, falling back to the device meanwhile
+      for (let i = 0; i < 16; i++) takeNonce();
+
+      // Without a pool, each nonce is fetched when needed
+      configure({ noncePoolSize: 0 });
+      assertEquals(stats().pooledNonces, 0);
+      takeNonce();
+
+      // No nonce was ever handed out twice
+      assertEquals(seen.size, 22);
+
+      // A pool too large to fill from the device in reasonable time is refused
+      assertThrows(
+        () => configure({ noncePoolSize: 4097 }),
+        NitroError,
+        "noncePoolSize",
+      );
+      assertEquals(currentOptions().noncePoolSize, 0);
+    } finally {
+      configure(previous);
+    }
+  },
+});
+
+Deno.test(async function attestPoolSizeTest() {
+  const previous = currentOptions();
+  try {
+    configure({ attestPoolSize: 1 });
+    const attestations = await Promise.all(
+      Array.from({ length: 8 }, (_, i) => attestAsync({ i })),
+    );
+    assertEquals(attestations.length, 8);
+    // Only one request was ever with the device at a time
+    assertEquals(stats().peakAsyncAttestations, 1);
+
+    // Oversized pools are capped at the device concurrency
+    configure({ attestPoolSize: 1_000_000 });
+    assertEquals(currentOptions().attestPoolSize < 1_000_000, true);
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test(function documentCacheTest() {
+  const previous = currentOptions();
+  try {
+    configure({ documentCacheSize: 4 });
+    const { documentCacheHits } = stats();
+
+    // Decoded by the first extraction only
+    documentTimestamp(quote);
+    nonzeroPcrs(quote);
+    requireDigest(quote, DigestAlgorithm.SHA384);
+    assertEquals(stats().documentCacheHits, documentCacheHits + 2);
+
+    // Off again, every extraction decodes
+    configure({ documentCacheSize: 0 });
+    documentTimestamp(quote);
+    assertEquals(stats().documentCacheHits, documentCacheHits + 2);
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test(function maxInputBytesTest() {
+  // Far beyond the default limit, with a genuine attestation at the front
+  const huge = new Uint8Array(8 * 1024 * 1024);
+  huge.set(quote);
+
+  assertEquals(verifyAttestation(huge, awsRoot), ErrorCode.INPUT_TOO_LARGE);
+  assertEquals(
+    assertThrows(() => documentFingerprint(huge), NitroError).code,
+    ErrorCode.INPUT_TOO_LARGE,
+  );
+  // Roots are bounded the same way
+  assertEquals(verifyAttestation(quote, huge), ErrorCode.INPUT_TOO_LARGE);
+
+  // Rejected on the length alone, before decoding: 0xff is not even CBOR
+  const garbage = new Uint8Array(huge.length).fill(0xff);
+  assertEquals(verifyAttestation(garbage, awsRoot), ErrorCode.INPUT_TOO_LARGE);
+  assertEquals(
+    verifyAttestation(garbage.subarray(0, 16), awsRoot),
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+
+  const previous = currentOptions();
+  try {
+    configure({ maxInputBytes: quote.length - 1 });
+    assertEquals(verifyAttestation(quote, awsRoot), ErrorCode.INPUT_TOO_LARGE);
+    configure({ maxInputBytes: quote.length });
+    assertEquals(verifyAttestation(quote, awsRoot), ErrorCode.OK);
+
+    assertThrows(
+      () => configure({ maxInputBytes: 0 }),
+      NitroError,
+      "maxInputBytes",
+    );
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test(function verificationCacheTest() {
+  const previous = currentOptions();
+  try {
+    configure({ verificationCacheTtlMs: 60_000 });
+    const { verificationCacheHits } = stats();
+
+    assertEquals(verifyAttestation(quote, awsRoot), ErrorCode.OK);
+    assertEquals(verifyAttestation(quote, awsRoot), ErrorCode.OK);
+    // The second verification was answered from the cache
+    assertEquals(stats().verificationCacheHits, verificationCacheHits + 1);
+
+    // Results are per document and root
+    const devRoot = Deno.readFileSync("./test_data/root-certificate.der");
+    assertEquals(verifyAttestation(quote, devRoot), ErrorCode.CHAIN_INVALID);
+    const tampered = Uint8Array.from(quote);
+    tampered[tampered.length - 1] ^= 1;
+    assertEquals(
+      verifyAttestation(tampered, awsRoot),
+      ErrorCode.SIGNATURE_INVALID,
+    );
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test({
+  name: "attestAndSignTest",
+  ignore: !dev,
+  async fn() {
+    const challenge = crypto.getRandomValues(new Uint8Array(32));
+    const { attestation, signature } = attestAndSign(challenge, "handshake");
+
+    const devRoot = Deno.readFileSync("./test_data/root-certificate.der");
+    assertEquals(verifyAttestation(attestation, devRoot), ErrorCode.OK);
+    const { payload } = validate(attestation);
+    assertEquals(new Uint8Array(payload.nonce as Uint8Array), challenge);
+
+    const spki = new Uint8Array(payload.public_key as Uint8Array);
+    assertEquals(verifyBoundPublicKey(attestation, spki), true);
+    const key = await crypto.subtle.importKey(
+      "spki",
+      spki,
+      { name: "ECDSA", namedCurve: "P-384" },
+      false,
+      ["verify"],
+    );
+    const algorithm = { name: "ECDSA", hash: "SHA-384" };
+    const signed = new Uint8Array([...CHALLENGE_CONTEXT, ...challenge]);
+    assertEquals(
+      await crypto.subtle.verify(algorithm, key, signature, signed),
+      true,
+    );
+
+    // The signature does not cover any other challenge
+    const other = new Uint8Array([...CHALLENGE_CONTEXT, ...challenge.reverse()]);
+    assertEquals(
+      await crypto.subtle.verify(algorithm, key, signature, other),
+      false,
+    );
+  },
+});
+
+Deno.test(async function strictCertValidityTest() {
+  const root = (pki: string) =>
+    Deno.readFileSync(`./test_data/${pki}/root-certificate.der`);
+  // Chains dated 2000 and 2090 respectively
+  const expired = await signedBy(quote, "expired");
+  const notYetValid = await signedBy(quote, "not-yet-valid");
+
+  const previous = currentOptions();
+  try {
+    assertEquals(verifyAttestation(expired, root("expired")), ErrorCode.OK);
+    assertEquals(
+      verifyAttestation(notYetValid, root("not-yet-valid")),
+      ErrorCode.OK,
+    );
+
+    configure({ strictCertValidity: true });
+    assertEquals(
+      verifyAttestation(expired, root("expired")),
+      ErrorCode.CERT_EXPIRED,
+    );
+    assertEquals(
+      verifyAttestation(notYetValid, root("not-yet-valid")),
+      ErrorCode.CERT_NOT_YET_VALID,
+    );
+    // The AWS quote's leaf was only valid for a few hours after it was taken
+    assertEquals(verifyAttestation(quote, awsRoot), ErrorCode.CERT_EXPIRED);
+    // Nor does a skew of days bring it back
+    configure({ certClockSkewMs: 7 * 24 * 3_600_000 });
+    assertEquals(verifyAttestation(quote, awsRoot), ErrorCode.CERT_EXPIRED);
+
+    // A cached chain result does not skip the validity check
+    configure({ verificationCacheTtlMs: 60_000 });
+    for (let i = 0; i < 2; i++) {
+      assertEquals(
+        verifyAttestation(expired, root("expired")),
+        ErrorCode.CERT_EXPIRED,
+      );
+    }
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test(function supportedRequestsTest() {
+  // The binding each request is (or would be) wrapped by
+  const requestBindings: Record<NsmRequest, string> = {
+    Attestation: "attest",
+    DescribeNSM: "in_enclave",
+    DescribePCR: "describe_pcr",
+    ExtendPCR: "extend_pcr",
+    GetRandom: "attest_with_fresh_nonce",
+    LockPCR: "lock_pcr",
+    LockPCRs: "lock_pcrs",
+  };
+  const supported = supportedRequests();
+  assertEquals(supported, ["Attestation", "DescribeNSM", "GetRandom"]);
+  for (const [request, binding] of Object.entries(requestBindings)) {
+    assertEquals(
+      supported.includes(request as NsmRequest),
+      binding in bindings,
+      request,
+    );
+  }
+});
+
+Deno.test({
+  name: "verifyAttestationChainTest",
+  ignore: !dev,
+  fn() {
+    const devRoot = Deno.readFileSync("./test_data/root-certificate.der");
+    // An inner enclave attests, and an outer one attests to having verified it
+    const inner = attest("inner");
+    assertEquals(verifyAttestation(inner, devRoot), ErrorCode.OK);
+    const outer = attest(documentFingerprint(inner));
+    assertEquals(verifyAttestationChain([inner, outer], devRoot), -1);
+
+    // The outer attestation does not vouch for any other inner one
+    const other = attest("other");
+    assertEquals(verifyAttestationChain([other, outer], devRoot), 1);
+    assertEquals(
+      lastErrorMessage(),
+      "policy violation: user_data does not commit to the previous document",
+    );
+
+    // Every link must verify in its own right
+    assertEquals(verifyAttestationChain([quote, outer], devRoot), 0);
+    assertEquals(lastErrorMessage(), "certificate chain does not verify");
+
+    assertEquals(
+      assertThrows(() => verifyAttestationChain([], devRoot), NitroError).code,
+      ErrorCode.INVALID_ARGUMENT,
+    );
+    // Failures of the input as a whole are not blamed on its first attestation
+    assertEquals(
+      assertThrows(
+        () => verifyAttestationChain([inner, outer], new Uint8Array([1])),
+        NitroError,
+      ).code,
+      ErrorCode.INVALID_ROOT,
+    );
+
+    const previous = currentOptions();
+    try {
+      // Each attestation is held to maxInputBytes, not the chain as a whole
+      const limit = Math.max(inner.length, outer.length);
+      configure({ maxInputBytes: limit });
+      assertEquals(verifyAttestationChain([inner, outer], devRoot), -1);
+      const oversized = new Uint8Array(limit + 1);
+      oversized.set(outer);
+      assertEquals(verifyAttestationChain([inner, oversized], devRoot), 1);
+      assertEquals(lastErrorMessage().includes("maxInputBytes"), true);
+    } finally {
+      configure(previous);
+    }
+  },
+});
+
+/**
+ * Run `code` in a process of its own, where the development driver is set up
+ * with `args`: it reads its PKI paths from the host's arguments once per
+ * process. Returns what `code` printed.
+ */
+async function inDevProcess(
+  code: string,
+  args: string[],
+  env?: Record<string, string>,
+): Promise<string> {
+  const { stdout } = await new Deno.Command(Deno.execPath(), {
+    args: ["eval", code, ...args],
+    env,
+  }).output();
+  return new TextDecoder().decode(stdout).trim();
+}
+
+/** Arguments setting up the development driver with a dated PKI's end certificate */
+function devPkiArgs(pki: string, intCerts: string[]): string[] {
+  const path = (name: string) => `./test_data/${pki}/${name}`;
+  return [
+    "--signing-key",
+    path("end-signing-key.der"),
+    "--end-cert",
+    path("end-certificate.der"),
+    ...intCerts.flatMap((cert) => [
+      "--int-certs",
+      path(`${cert}-certificate.der`),
+    ]),
+  ];
+}
+
+Deno.test({
+  name: "intCertsOrderTest",
+  ignore: !dev,
+  async fn() {
+    const initDriverWith = (intCerts: string[]) =>
+      inDevProcess(
+        `import { initDriver } from "./mod.ts";
+        try {
+          initDriver();
+          console.log("ok");
+        } catch (err) {
+          console.log(err.code, err.message);
+        }`,
+        devPkiArgs("expired", intCerts),
+      );
+
+    // Ordered from the end certificate's issuer towards the root
+    assertEquals(await initDriverWith(["int", "root"]), "ok");
+
+    const failure = await initDriverWith(["root", "int"]);
+    assertEquals(failure.startsWith(`${ErrorCode.DRIVER_UNAVAILABLE} `), true);
+    assertEquals(
+      failure.includes("end certificate was not issued by intermediate 0"),
+      true,
+    );
+  },
+});
+
+Deno.test(function devCaChainTest() {
+  if (!dev) {
+    assertEquals(
+      assertThrows(() => devCaChain(), NitroError).code,
+      ErrorCode.DRIVER_UNAVAILABLE,
+    );
+    return;
+  }
+
+  const chain = devCaChain();
+  const attestation = attest("dev CA chain");
+  assertEquals(verifyAttestation(attestation, chain[0]), ErrorCode.OK);
+  // The default chain's root is not in the cabundle; the rest is
+  assertEquals(chain.slice(1), validate(attestation).payload.cabundle);
+});
+
+Deno.test(function emitTestVectorTest() {
+  if (!dev) {
+    assertEquals(
+      assertThrows(() => emitTestVector(), NitroError).code,
+      ErrorCode.DRIVER_UNAVAILABLE,
+    );
+    return;
+  }
+
+  const vector = emitTestVector();
+  assertEquals(emitTestVector(), vector);
+  assertEquals(vector, Deno.readFileSync("./test_data/test-vector.cbor"));
+  const root = Deno.readFileSync("./test_data/root-certificate.der");
+  assertEquals(verifyAttestation(vector, root), ErrorCode.OK);
+  assertEquals(documentTimestamp(vector), Date.UTC(2030, 0, 1));
+});
+
+Deno.test(function testClockTest() {
+  if (!dev) {
+    assertEquals(
+      assertThrows(() => setTestClock(0), NitroError).code,
+      ErrorCode.DRIVER_UNAVAILABLE,
+    );
+    return;
+  }
+
+  const vector = emitTestVector();
+  const root = Deno.readFileSync("./test_data/root-certificate.der");
+  const created = documentTimestamp(vector);
+  try {
+    setTestClock(created + 60_000);
+    assertEquals(documentIsFresh(vector, 60_000), true);
+    assertEquals(documentIsFresh(vector, 59_999), false);
+    assertEquals(
+      verifyWithPolicy(vector, { root, maxAgeMs: 60_000 }),
+      ErrorCode.OK,
+    );
+    assertEquals(
+      verifyWithPolicy(vector, { root, maxAgeMs: 59_999 }),
+      ErrorCode.POLICY_VIOLATION,
+    );
+    assertEquals(lastErrorMessage(), "policy violation: document too old");
+
+    // Attestations from the future are fresh only within the clock skew
+    setTestClock(created - 60_000);
+    assertEquals(documentIsFresh(vector, 1), true);
+    setTestClock(created - 60_001);
+    assertEquals(documentIsFresh(vector, Number.MAX_SAFE_INTEGER), false);
+    assertEquals(
+      verifyWithPolicy(vector, { root, maxAgeMs: 60_000 }),
+      ErrorCode.POLICY_VIOLATION,
+    );
+    assertEquals(
+      lastErrorMessage(),
+      "policy violation: document timestamp is in the future",
+    );
+  } finally {
+    setTestClock();
+  }
+});
+
+Deno.test({
+  name: "serveSocketTest",
+  ignore: !dev || !("serve_socket" in bindings),
+  async fn() {
+    const path = `${await Deno.makeTempDir()}/attest.sock`;
+    serveSocket(path);
+    assertEquals(
+      assertThrows(() => serveSocket(path), NitroError).code,
+      ErrorCode.INVALID_ARGUMENT,
+    );
+
+    const conn = await Deno.connect({ transport: "unix", path });
+    try {
+      // User data is attested as given, here encoded as `attest` would
+      const request = encode({ userData: encode("over the socket") });
+      const frame = new Uint8Array(4 + request.length);
+      new DataView(frame.buffer).setUint32(0, request.length);
+      frame.set(request, 4);
+      await conn.write(frame);
+
+      const header = new Uint8Array(8);
+      await readFull(conn, header);
+      const view = new DataView(header.buffer);
+      assertEquals(view.getInt32(0), ErrorCode.OK);
+      const attestation = new Uint8Array(view.getUint32(4));
+      await readFull(conn, attestation);
+
+      const root = Deno.readFileSync("./test_data/root-certificate.der");
+      assertEquals(verifyAttestation(attestation, root), ErrorCode.OK);
+      assertEquals(validate(attestation).payload.user_data, "over the socket");
+    } finally {
+      conn.close();
+    }
+  },
+});
+
+/** Fill `buffer` from `conn`, however the bytes arrive */
+async function readFull(conn: Deno.Conn, buffer: Uint8Array): Promise<void> {
+  for (let read = 0; read < buffer.length;) {
+    const n = await conn.read(buffer.subarray(read));
+    if (n === null) throw new Error("connection closed");
+    read += n;
+  }
+}
+
+Deno.test({
+  name: "verifyDigestConsistencyTest",
+  ignore: !dev,
+  async fn() {
+    verifyDigestConsistency();
+
+    const output = await inDevProcess(
+      `import { verifyDigestConsistency } from "./mod.ts";
+      try {
+        verifyDigestConsistency();
+        console.log("ok");
+      } catch (err) {
+        console.log(err.code);
+      }`,
+      ["--describe-digest", "SHA256"],
+    );
+    assertEquals(output, `${ErrorCode.DIGEST_MISMATCH}`);
+  },
+});
+
+Deno.test({
+  name: "selfCheckTest",
+  ignore: !dev,
+  async fn() {
+    selfCheck();
+
+    // A consistent chain, but not one leading to the development CA
+    const output = await inDevProcess(
+      `import { selfCheck } from "./mod.ts";
+      try {
+        selfCheck();
+        console.log("ok");
+      } catch (err) {
+        console.log(err.code);
+      }`,
+      devPkiArgs("not-yet-valid", ["int", "root"]),
+    );
+    assertEquals(output, `${ErrorCode.CHAIN_INVALID}`);
+  },
+});
+
+Deno.test({
+  name: "closeDriverTest",
+  ignore: !dev,
+  async fn() {
+    // A device taking 5s per request, one request at a time
+    const output = await inDevProcess(
+      `import { attestAsync, closeDriver, configure } from "./mod.ts";
+      configure({ attestPoolSize: 1 });
+      const pending = Array.from({ length: 4 }, (_, i) => attestAsync({ i }));
+      await new Promise((resolve) => setTimeout(resolve, 100));
+      const closed = performance.now();
+      closeDriver();
+      const settled = await Promise.allSettled([...pending, attestAsync(4)]);
+      console.log(JSON.stringify({
+        codes: settled.map((result) => result.reason?.code),
+        elapsed: performance.now() - closed,
+      }));`,
+      ["--attest-delay-ms", "5000"],
+    );
+    const { codes, elapsed } = JSON.parse(output);
+    // The request with the device, those waiting for it and the late one
+    assertEquals(codes, Array(5).fill(ErrorCode.CANCELLED));
+    assertEquals(elapsed < 1000, true);
+  },
+});
+
+Deno.test({
+  name: "unexpectedResponseTest",
+  ignore: !dev,
+  async fn() {
+    const output = await inDevProcess(
+      `import { attest, attestAndSign, lastErrorMessage } from "./mod.ts";
+      const codes = [() => attest("a"), () => attestAndSign(new Uint8Array(32))]
+        .map((request) => {
+          try {
+            request();
+          } catch (error) {
+            return error.code;
+          }
+        });
+      console.log(JSON.stringify({ codes, message: lastErrorMessage() }));`,
+      ["--unexpected-responses"],
+    );
+    const { codes, message } = JSON.parse(output);
+    assertEquals(codes, [
+      ErrorCode.UNEXPECTED_RESPONSE,
+      ErrorCode.UNEXPECTED_RESPONSE,
+    ]);
+    assertEquals(message.includes("unexpected response"), true);
+  },
+});
+
+Deno.test({
+  name: "attestQuotaRefundTest",
+  ignore: !dev,
+  async fn() {
+    // Attestations the driver fails to make leave the allowance untouched
+    const output = await inDevProcess(
+      `import { attest, configure } from "./mod.ts";
+      configure({ attestQuotaBytes: 1000 });
+      const codes = [1, 2, 3].map(() => {
+        try {
+          attest(new Uint8Array(600));
+        } catch (error) {
+          return error.code;
+        }
+      });
+      console.log(JSON.stringify(codes));`,
+      ["--unexpected-responses"],
+    );
+    assertEquals(
+      JSON.parse(output),
+      Array(3).fill(ErrorCode.UNEXPECTED_RESPONSE),
+    );
+  },
+});
+
+Deno.test({
+  name: "attestAsyncErrorTest",
+  ignore: !dev,
+  async fn() {
+    // Failures on the pool thread reject with their own code and message
+    const settle = `const settle = (promise) => promise.then(
+        (attestation) => ({ length: attestation.length }),
+        (error) => ({ code: error.code, message: error.message }),
+      );`;
+    const unexpected = JSON.parse(
+      await inDevProcess(
+        `import { attestAsync } from "./mod.ts";
+        ${settle}
+        console.log(JSON.stringify(await settle(attestAsync("a"))));`,
+        ["--unexpected-responses"],
+      ),
+    );
+    assertEquals(unexpected.code, ErrorCode.UNEXPECTED_RESPONSE);
+    assertEquals(unexpected.message.includes("unexpected response"), true);
+
+    const [refused, attested] = JSON.parse(
+      await inDevProcess(
+        `import { attestAsync, configure } from "./mod.ts";
+        ${settle}
+        configure({ attestQuotaBytes: 100 });
+        console.log(JSON.stringify([
+          await settle(attestAsync(new Uint8Array(600))),
+          await settle(attestAsync("a")),
+        ]));`,
+        [],
+      ),
+    );
+    assertEquals(refused.code, ErrorCode.QUOTA_EXCEEDED);
+    assertEquals(attested.length > 0, true);
+  },
+});
+
+Deno.test({
+  name: "vendorExtensionTest",
+  ignore: !dev,
+  async fn() {
+    const output = await inDevProcess(
+      `import {
+        attest,
+        nonzeroPcrs,
+        validate,
+        verifyWithPolicy,
+      } from "./mod.ts";
+      const root = Deno.readFileSync("./test_data/root-certificate.der");
+      const attestation = attest("extended");
+      const { payload } = validate(attestation);
+      const pcrs = { 0: [payload.pcrs[0]] };
+      console.log(JSON.stringify({
+        extension: payload["x-vendor"],
+        userData: payload.user_data,
+        nonzeroPcrs: nonzeroPcrs(attestation),
+        verified: verifyWithPolicy(attestation, { root, pcrs }),
+      }));`,
+      ["--vendor-extension", "x-vendor=acme"],
+    );
+    // The field AWS documents lack is there, and changes nothing else
+    assertEquals(JSON.parse(output), {
+      extension: "acme",
+      userData: "extended",
+      nonzeroPcrs: 0,
+      verified: ErrorCode.OK,
+    });
+  },
+});
+
+Deno.test(function driverInitMicrosTest() {
+  initDriver();
+  const { driverInitMicros } = stats();
+  assertEquals(driverInitMicros > 0, true);
+  // Recorded once, when the driver was first set up
+  initDriver();
+  attest("after init");
+  assertEquals(stats().driverInitMicros, driverInitMicros);
+});
+
+Deno.test({
+  name: "sameInstanceTest",
+  ignore: !dev,
+  fn() {
+    const previous = currentOptions();
+    try {
+      configure({ embedBootSessionId: true });
+      const first = attest("first");
+      assertEquals(sameInstance(first, attest("second")), true);
+
+      // As if the enclave had restarted in between
+      const restarted = withPayload(first, (payload) => {
+        const header = decode(payload.get("user_data") as Uint8Array);
+        header.bootSessionId = crypto.getRandomValues(new Uint8Array(16));
+        payload.set("user_data", encode(header));
+      });
+      assertEquals(documentBootSessionId(restarted)?.length, 16);
+      assertEquals(sameInstance(first, restarted), false);
+      const otherEnclave = withPayload(first, (payload) => {
+        payload.set("module_id", "i-0123456789abcdef0-enc0123456789abcdef");
+      });
+      assertEquals(sameInstance(first, otherEnclave), false);
+
+      // Without boot session ids only the module ID can be compared
+      configure({ embedBootSessionId: false });
+      const unwrapped = attest("unwrapped");
+      assertEquals(sameInstance(unwrapped, attest("again")), true);
+      assertEquals(sameInstance(first, unwrapped), false);
+
+      assertEquals(
+        assertThrows(() => sameInstance(first, new Uint8Array([1])), NitroError)
+          .code,
+        ErrorCode.MALFORMED_DOCUMENT,
+      );
+    } finally {
+      configure(previous);
+    }
+  },
+});
+
+Deno.test(function bootSessionIdTest() {
+  const id = bootSessionId();
+  assertEquals(id.length, 16);
+  assertEquals(bootSessionId(), id);
+
+  const previous = currentOptions();
+  try {
+    assertEquals(documentBootSessionId(attest("unwrapped")), undefined);
+
+    configure({ embedBootSessionId: true });
+    const first = attest("first");
+    const second = attest("second");
+    assertEquals(documentBootSessionId(first), id);
+    assertEquals(documentBootSessionId(second), id);
+
+    // The caller's data is kept alongside the id
+    const { bootSessionId: embedded, userData } = validate(first).payload
+      .user_data as { bootSessionId: Uint8Array; userData: Uint8Array };
+    assertEquals(new Uint8Array(embedded), id);
+    assertEquals(decode(userData), "first");
+  } finally {
+    configure(previous);
+  }
+
+  assertEquals(
+    assertThrows(() => documentBootSessionId(new Uint8Array([1])), NitroError)
+      .code,
+    ErrorCode.MALFORMED_DOCUMENT,
+  );
+});
+
+Deno.test({
+  name: "inEnclaveTest",
+  ignore: !dev,
+  fn() {
+    assertEquals(inEnclave(), false);
+    // The result is decided once
+    assertEquals(inEnclave(), false);
+  },
+});
+
+Deno.test({
+  name: "nsmDriverVersionTest",
+  fn() {
+    const version = nsmDriverVersion();
+    assertNotEquals(version, "");
+    assertEquals(version.startsWith("nsm-nitro-enclave-utils "), true);
+    assertEquals(version.includes("unknown"), false);
+    if (dev) {
+      assertEquals(version.endsWith("; development driver"), true);
+    }
+  },
+});
+
+Deno.test({
+  name: "attestKeyTest",
+  ignore: !dev,
+  async fn() {
+    const { publicKey } = await crypto.subtle.generateKey(
+      { name: "ECDSA", namedCurve: "P-256" },
+      true,
+      ["sign", "verify"],
+    );
+    const spki = new Uint8Array(
+      await crypto.subtle.exportKey("spki", publicKey),
+    );
+
+    const { payload } = validate(attestKey(spki));
+    assertEquals(new Uint8Array(payload.public_key as Uint8Array), spki);
+    assertEquals(payload.user_data, null);
+    assertEquals(payload.nonce, null);
+
+    const nonce = crypto.getRandomValues(new Uint8Array(16));
+    const withNonce = validate(attestKey(spki, nonce)).payload;
+    assertEquals(new Uint8Array(withNonce.nonce as Uint8Array), nonce);
+    assertEquals(withNonce.user_data, null);
+
+    const previous = currentOptions();
+    try {
+      // No user data is invented to carry a boot session id in
+      configure({ embedBootSessionId: true });
+      const bare = attestKey(spki);
+      assertEquals(validate(bare).payload.user_data, null);
+      assertEquals(documentBootSessionId(bare), undefined);
+    } finally {
+      configure(previous);
+    }
+
+    assertEquals(
+      assertThrows(() => attestKey(new Uint8Array([1, 2, 3])), NitroError)
+        .code,
+      ErrorCode.INVALID_ARGUMENT,
+    );
+  },
+});
+
+Deno.test({
+  name: "verifyAndExportKeyTest",
+  ignore: !dev,
+  async fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const { publicKey } = await crypto.subtle.generateKey(
+      { name: "ECDSA", namedCurve: "P-384" },
+      true,
+      ["sign", "verify"],
+    );
+    const spki = new Uint8Array(
+      await crypto.subtle.exportKey("spki", publicKey),
+    );
+
+    const exported = verifyAndExportKey(attestKey(spki), { root });
+    assertEquals(exported, spki);
+    const imported = await crypto.subtle.importKey(
+      "spki",
+      exported!,
+      { name: "ECDSA", namedCurve: "P-384" },
+      true,
+      ["verify"],
+    );
+    assertEquals(
+      new Uint8Array(await crypto.subtle.exportKey("raw", imported)),
+      new Uint8Array(await crypto.subtle.exportKey("raw", publicKey)),
+    );
+
+    assertEquals(verifyAndExportKey(attest("no key"), { root }), null);
+    const nonce = new Uint8Array([1, 2, 3]);
+    assertEquals(
+      assertThrows(
+        () => verifyAndExportKey(attestKey(spki), { root, nonce }),
+        NitroError,
+      ).code,
+      ErrorCode.POLICY_VIOLATION,
+    );
+  },
+});
+
+Deno.test({
+  name: "verifySignatureTest",
+  ignore: !dev,
+  async fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const { publicKey, privateKey } = await crypto.subtle.generateKey(
+      { name: "ECDSA", namedCurve: "P-384" },
+      true,
+      ["sign", "verify"],
+    );
+    const attestation = attestKey(
+      new Uint8Array(await crypto.subtle.exportKey("spki", publicKey)),
+    );
+    const message = new TextEncoder().encode("application data");
+    const signature = new Uint8Array(
+      await crypto.subtle.sign(
+        { name: "ECDSA", hash: "SHA-384" },
+        privateKey,
+        message,
+      ),
+    );
+
+    assertEquals(
+      verifySignature(message, signature, attestation, root),
+      ErrorCode.OK,
+    );
+    assertEquals(
+      verifySignature(
+        new TextEncoder().encode("other data"),
+        signature,
+        attestation,
+        root,
+      ),
+      ErrorCode.SIGNATURE_INVALID,
+    );
+    // The attestation must verify, and bind a key
+    assertEquals(
+      verifySignature(message, signature, attestation, awsRoot),
+      ErrorCode.CHAIN_INVALID,
+    );
+    assertEquals(
+      verifySignature(message, signature, attest("no key"), root),
+      ErrorCode.POLICY_VIOLATION,
+    );
+
+    // The bound key's challenge signatures, over the context and challenge
+    const challenge = crypto.getRandomValues(new Uint8Array(32));
+    const signed = attestAndSign(challenge);
+    assertEquals(
+      verifySignature(
+        new Uint8Array([...CHALLENGE_CONTEXT, ...challenge]),
+        signed.signature,
+        signed.attestation,
+        root,
+      ),
+      ErrorCode.OK,
+    );
+  },
+});
+
+Deno.test(function rateLimitTest() {
+  const previous = currentOptions();
+  try {
+    configure({ rateLimitCapacity: 2, rateLimitRefillPerSecond: 1 });
+    attest("first", { tag: "noisy" });
+    attest("second", { tag: "noisy" });
+    assertEquals(
+      assertThrows(() => attest("third", { tag: "noisy" }), NitroError).code,
+      ErrorCode.RATE_LIMITED,
+    );
+
+    // Other tags, and untagged calls, have allowances of their own
+    assertEquals(attest("first", { tag: "quiet" }).length > 0, true);
+    assertEquals(attest("untagged").length > 0, true);
+
+    assertThrows(
+      () => configure({ rateLimitRefillPerSecond: 0 }),
+      NitroError,
+      "rateLimitRefillPerSecond",
+    );
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test({
+  name: "nonceReplayTest",
+  ignore: !dev,
+  async fn() {
+    const root = Deno.readFileSync("./test_data/root-certificate.der");
+    const previous = currentOptions();
+    try {
+      configure({ seenNonceCapacity: 16, seenNonceTtlMs: 1000 });
+      const attestation = attestWithFreshNonce("once");
+      assertEquals(verifyWithPolicy(attestation, { root }), ErrorCode.OK);
+      assertEquals(
+        verifyWithPolicy(attestation, { root }),
+        ErrorCode.NONCE_REPLAYED,
+      );
+      assertEquals(lastErrorMessage(), "nonce has already been accepted");
+
+      // Neither a rejection nor an explanation uses the nonce up
+      const other = attestWithFreshNonce("other");
+      const wrongModule = { root, moduleIds: ["another-module"] };
+      assertEquals(
+        verifyWithPolicy(other, wrongModule),
+        ErrorCode.POLICY_VIOLATION,
+      );
+      explainVerification(other, { root });
+      assertEquals(verifyWithPolicy(other, { root }), ErrorCode.OK);
+
+      // Attestations without a nonce are unaffected
+      const unbound = attest("no nonce");
+      assertEquals(verifyWithPolicy(unbound, { root }), ErrorCode.OK);
+      assertEquals(verifyWithPolicy(unbound, { root }), ErrorCode.OK);
+
+      // Remembered only for the TTL
+      await new Promise((resolve) => setTimeout(resolve, 1100));
+      assertEquals(verifyWithPolicy(attestation, { root }), ErrorCode.OK);
+
+      assertThrows(
+        () => configure({ seenNonceTtlMs: 0 }),
+        NitroError,
+        "seenNonceTtlMs",
+      );
+    } finally {
+      configure(previous);
+    }
+  },
+});
+
+Deno.test({
+  name: "attestQuotaTest",
+  ignore: !dev,
+  async fn() {
+    // Each input encodes to a little over 600 bytes of user data
+    const large = new Uint8Array(600);
+    const previous = currentOptions();
+    try {
+      configure({ attestQuotaBytes: 1000, attestQuotaWindowMs: 300 });
+      attest(large);
+      attest(new Uint8Array(300));
+      assertEquals(
+        assertThrows(() => attest(large), NitroError).code,
+        ErrorCode.QUOTA_EXCEEDED,
+      );
+      // Every caller shares the allowance
+      assertThrows(() => attest(large, { tag: "other" }), NitroError);
+      // Attestations without user data cost nothing
+      const key = await crypto.subtle.generateKey(
+        { name: "ECDSA", namedCurve: "P-384" },
+        true,
+        ["sign", "verify"],
+      );
+      attestKey(
+        new Uint8Array(await crypto.subtle.exportKey("spki", key.publicKey)),
+      );
+
+      // Refused until the earlier attestations leave the window
+      await new Promise((resolve) => setTimeout(resolve, 350));
+      assertEquals(attest(large).length > 0, true);
+
+      assertThrows(
+        () => configure({ attestQuotaWindowMs: 0 }),
+        NitroError,
+        "attestQuotaWindowMs",
+      );
+    } finally {
+      configure(previous);
+    }
+  },
+});
+
+Deno.test(async function relaxPcrChecksTest() {
+  const root = Deno.readFileSync("./test_data/expired/root-certificate.der");
+  // 40 PCRs of 20 bytes each, as a non-AWS module might report
+  const unusual = await signedBy(
+    withPayload(quote, (payload) => {
+      payload.set(
+        "pcrs",
+        new Map(Array.from({ length: 40 }, (_, i) => [i, new Uint8Array(20)])),
+      );
+    }),
+    "expired",
+  );
+
+  const previous = currentOptions();
+  try {
+    assertEquals(
+      verifyAttestation(unusual, root),
+      ErrorCode.MALFORMED_DOCUMENT,
+    );
+
+    configure({ relaxPcrChecks: true });
+    assertEquals(verifyAttestation(unusual, root), ErrorCode.OK);
+    // The signature is still checked
+    const tampered = withPayload(unusual, (payload) => {
+      payload.set("module_id", "tampered");
+    });
+    assertEquals(
+      verifyAttestation(tampered, root),
+      ErrorCode.SIGNATURE_INVALID,
+    );
+  } finally {
+    configure(previous);
+  }
+});
+
+Deno.test({
+  name: "requireDigestTest",
+  ignore: !dev,
+  fn() {
+    const sha384 = attest("digest");
+    assertEquals(requireDigest(sha384, DigestAlgorithm.SHA384), true);
+    assertEquals(requireDigest(sha384, DigestAlgorithm.SHA512), false);
+
+    const sha256 = withPayload(sha384, (payload) => {
+      payload.set("digest", "SHA256");
+    });
+    assertEquals(requireDigest(sha256, DigestAlgorithm.SHA384), false);
+    assertEquals(requireDigest(sha256, DigestAlgorithm.SHA256), true);
+
+    // Signing algorithms are not digests
+    assertEquals(
+      assertThrows(() => requireDigest(sha384, CoseAlgorithm.ES384), NitroError)
+        .code,
+      ErrorCode.INVALID_ARGUMENT,
+    );
+  },
 });
@@ -0,0 +1,50 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The enclave's bound key
+//!
+//! A P-384 key pair generated inside the enclave the first time it is needed,
+//! from the kernel RNG (fed by the NSM's hardware RNG in an enclave). The
+//! private key only ever exists in this process's memory: it is never exported,
+//! persisted or shared with the host, and a new one is generated every time the
+//! library is loaded. Embedding the public key in an attestation therefore
+//! proves that whoever holds the private key is the attested enclave.
+//!
+//! Challenges are signed over [`CHALLENGE_CONTEXT`] followed by the challenge,
+//! so a relying party cannot use the key to sign arbitrary messages.
+
+use p384::ecdsa::{Signature, SigningKey, signature::Signer};
+use p384::pkcs8::EncodePublicKey;
+use rand_core::OsRng;
+use std::sync::LazyLock;
+
+/// Prefix of every message signed by [`sign_challenge`]
+pub(crate) const CHALLENGE_CONTEXT: &[u8] = b"deno-nitro bound key challenge v1\0";
+
+static KEY: LazyLock<SigningKey> = LazyLock::new(|| SigningKey::random(&mut OsRng));
+
+/// DER SubjectPublicKeyInfo of the bound key
+pub(crate) fn public_key_der() -> Vec<u8> {
+    KEY.verifying_key()
+        .to_public_key_der()
+        .map(|der| der.into_vec())
+        .unwrap_or_default()
+}
+
+/// ECDSA P-384 / SHA-384 signature over `CHALLENGE_CONTEXT || challenge`
+pub(crate) fn sign_challenge(challenge: &[u8]) -> Signature {
+    KEY.sign(&[CHALLENGE_CONTEXT, challenge].concat())
+}
@@ -0,0 +1,133 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cache of certificate chain and signature results
+//!
+//! Entries are keyed by the SHA-256 of the document and the root it was checked
+//! against, and hold only the outcome of the chain and signature checks, which
+//! never change for a given document and root. Anything time-sensitive (age,
+//! nonces) is left to the caller to check on every verification. The cache is
+//! off until given a TTL.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// Entries kept when no size is configured
+pub(crate) const DEFAULT_SIZE: usize = 1024;
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    ttl: Duration::ZERO,
+    size: DEFAULT_SIZE,
+    entries: None,
+});
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+
+type Key = [u8; 32];
+
+/// When an entry expires, and the result it holds
+type Entry = (Instant, Result<(), Error>);
+
+struct Cache {
+    /// How long results are kept, or zero when caching is off
+    ttl: Duration,
+    size: usize,
+    /// Allocated on first insert
+    entries: Option<HashMap<Key, Entry>>,
+}
+
+fn lock() -> MutexGuard<'static, Cache> {
+    CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Return the cached result for `document` against `root`, or compute and cache it
+pub(crate) fn get_or_verify(
+    document: &[u8],
+    root: &[u8],
+    verify: impl FnOnce() -> Result<(), Error>,
+) -> Result<(), Error> {
+    if lock().ttl.is_zero() {
+        return verify();
+    }
+
+    let key: Key = Sha256::new()
+        .chain_update(Sha256::digest(document))
+        .chain_update(root)
+        .finalize()
+        .into();
+    let now = Instant::now();
+    if let Some((expires, result)) = lock()
+        .entries
+        .as_ref()
+        .and_then(|entries| entries.get(&key))
+        && *expires > now
+    {
+        HITS.fetch_add(1, Ordering::Relaxed);
+        return *result;
+    }
+
+    // Verify without holding the lock, so other verifications can proceed
+    let result = verify();
+    let mut cache = lock();
+    let (ttl, size) = (cache.ttl, cache.size);
+    if !ttl.is_zero() {
+        let entries = cache.entries.get_or_insert_with(HashMap::new);
+        if entries.len() >= size && !entries.contains_key(&key) {
+            entries.retain(|_, (expires, _)| *expires > now);
+        }
+        if entries.len() >= size
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (expires, _))| *expires)
+                .map(|(key, _)| *key)
+        {
+            entries.remove(&oldest);
+        }
+        entries.insert(key, (now + ttl, result));
+    }
+    result
+}
+
+/// Set how long results are kept (zero turns the cache off) and how many are
+/// kept, dropping every cached result
+pub(crate) fn configure(ttl: Option<Duration>, size: Option<usize>) {
+    let mut cache = lock();
+    if let Some(ttl) = ttl {
+        cache.ttl = ttl;
+    }
+    if let Some(size) = size {
+        cache.size = size;
+    }
+    cache.entries = None;
+}
+
+pub(crate) fn ttl() -> Duration {
+    lock().ttl
+}
+
+pub(crate) fn size() -> usize {
+    lock().size
+}
+
+/// Verifications answered from the cache since the library was loaded
+pub(crate) fn hits() -> u64 {
+    HITS.load(Ordering::Relaxed)
+}
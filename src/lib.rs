@@ -23,6 +23,10 @@
  *
  * - `nitro` (default): Uses actual AWS Nitro hardware for attestation
  * - `dev`: Uses a mock driver for local development and testing
+ * - `dev-embedded`: Like `dev`, with the default test PKI built in rather than
+ *   read from `./test_data/` at runtime
+ * - `socket`: Adds `serve_socket`, serving attestations to other processes on
+ *   the host over a Unix domain socket
  *
  * ## Architecture
  *
@@ -31,111 +35,3133 @@
  * it connects to the real NSM. In development mode (`dev` feature), it uses a
  * mock driver with test certificates for local development.
  *
+ * ## Verification
+ *
+ * `verify_attestation` checks a document's certificate chain and COSE signature
+ * against a trusted root. The root is, in order of preference, the one passed by
+ * the caller, the DER file named by `DENO_NITRO_ROOT_CERT`, or the AWS Nitro
+ * Enclaves root certificate embedded at build time. The parsing and checks
+ * themselves live in the `verify` module, free of any FFI or driver code; the
+ * bindings wrap them with caching, options and error reporting.
+ *
  * ## Safety
  *
  * This module uses FFI to expose functions to Deno through `deno_bindgen`.
  * The attestation functions are safe to call from multiple threads.
  */
 
+// `deno_bindgen` wraps every binding in a safe `extern "C"` shim taking raw pointers
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+use ciborium_ll::{Decoder, Encoder, Header};
+use coset::{CborSerializable, CoseSign1, iana};
 use deno_bindgen::deno_bindgen;
+#[cfg(feature = "nitro")]
 use nsm_nitro_enclave_utils::driver::nitro::Nitro;
 use nsm_nitro_enclave_utils::{
     api::{
         ByteBuf,
-        nsm::{Request, Response},
+        nsm::{AttestationDoc, Request, Response},
     },
     driver::Driver,
 };
 #[cfg(not(feature = "nitro"))]
 use nsm_nitro_enclave_utils::{
-    api::{DecodePrivateKey, SecretKey},
+    api::{
+        DecodePrivateKey, SecretKey,
+        nsm::{Digest as NsmDigest, ErrorCode},
+    },
     pcr::Pcrs,
 };
-use std::sync::Arc;
+use p384::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+#[cfg(not(feature = "nitro"))]
+use p384::ecdsa::{SigningKey, signature::Signer};
+use p384::pkcs8::DecodePublicKey;
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256, Sha384};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+#[cfg(not(feature = "nitro"))]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, OnceLock};
+use subtle::ConstantTimeEq;
+use x509_cert::{Certificate, der::Decode, spki::SubjectPublicKeyInfoRef};
+#[cfg(not(feature = "nitro"))]
+use zeroize::Zeroize;
 
 #[macro_use]
 extern crate lazy_static;
 
-#[cfg(feature = "dev")]
+mod bound_key;
+mod cache;
+mod nonce_pool;
+mod parsed;
+mod pool;
+mod quota;
+mod rate_limit;
+mod replay;
+mod serialize;
+#[cfg(feature = "socket")]
+mod socket;
+mod stats;
+mod verify;
+
+#[cfg(not(feature = "nitro"))]
 #[derive(clap::Parser, Debug)]
+// The process arguments belong to whatever host loaded us (e.g. `deno test`),
+// so fall back to the defaults rather than exiting on arguments we don't know.
+#[command(ignore_errors = true)]
 struct Args {
-    #[arg(
-        long,
-        allow_hyphen_values = true,
-        default_value = "./test_data/end-signing-key.der"
-    )]
-    signing_key: std::path::PathBuf,
-    #[arg(
-        long,
-        allow_hyphen_values = true,
-        default_value = "./test_data/end-certificate.der"
-    )]
-    end_cert: std::path::PathBuf,
-    #[arg(
-        long,
-        allow_hyphen_values = true,
-        default_value = "./test_data/int-certificate.der"
-    )]
+    /// Defaults to `test_data/end-signing-key.der`
+    #[arg(long, allow_hyphen_values = true)]
+    signing_key: Option<std::path::PathBuf>,
+    /// Defaults to `test_data/end-certificate.der`
+    #[arg(long, allow_hyphen_values = true)]
+    end_cert: Option<std::path::PathBuf>,
+    /// Intermediate certificates in chain order: the end certificate's issuer
+    /// first, then its issuer, and so on towards the root. Defaults to
+    /// `test_data/int-certificate.der`
+    #[arg(long, allow_hyphen_values = true)]
     int_certs: Vec<std::path::PathBuf>,
+    /// Hold every request this long before answering it, to stand in for a slow
+    /// device
+    #[arg(long)]
+    attest_delay_ms: Option<u64>,
+    /// Add a `KEY=VALUE` text field to the top level of every document's
+    /// payload, as a vendor extension AWS documents never carry, to test that
+    /// verifiers ignore fields they do not know
+    #[arg(long, value_parser = parse_vendor_extension)]
+    vendor_extension: Option<(String, String)>,
+    /// Answer attestation requests with `LockPCRs`, as firmware speaking another
+    /// protocol version might, to test how unexpected responses are handled
+    #[arg(long)]
+    unexpected_responses: bool,
+    /// Digest the driver reports to `DescribeNSM` (`SHA256`, `SHA384` or
+    /// `SHA512`), defaulting to the SHA-384 its documents declare; any other
+    /// stands in for firmware that disagrees with its own documents
+    #[arg(long, value_parser = parse_digest)]
+    describe_digest: Option<NsmDigest>,
+    /// The host's own positional arguments (e.g. `eval <code>` of `deno eval`),
+    /// collected so that the flags after them are still parsed
+    #[arg(hide = true)]
+    host_args: Vec<String>,
+}
+
+#[cfg(not(feature = "nitro"))]
+fn parse_vendor_extension(field: &str) -> Result<(String, String), String> {
+    field
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| "expected KEY=VALUE".to_string())
+}
+
+#[cfg(not(feature = "nitro"))]
+fn parse_digest(digest: &str) -> Result<NsmDigest, String> {
+    match digest {
+        "SHA256" => Ok(NsmDigest::SHA256),
+        "SHA384" => Ok(NsmDigest::SHA384),
+        "SHA512" => Ok(NsmDigest::SHA512),
+        _ => Err("expected SHA256, SHA384 or SHA512".to_string()),
+    }
+}
+
+/// Read a dev PKI file from `path` if one was given, otherwise the `test_data/`
+/// default: baked in with the `dev-embedded` feature, or read relative to the CWD.
+#[cfg(not(feature = "nitro"))]
+macro_rules! dev_file {
+    ($path:expr, $default:literal) => {
+        match $path {
+            Some(path) => std::fs::read(path).unwrap(),
+            #[cfg(feature = "dev-embedded")]
+            None => include_bytes!(concat!("../test_data/", $default)).to_vec(),
+            #[cfg(not(feature = "dev-embedded"))]
+            None => std::fs::read(concat!("./test_data/", $default)).unwrap(),
+        }
+    };
 }
 
 lazy_static! {
-    static ref NITRO: Arc<Nitro> = Arc::new({
+    /// The driver, or why it could not be set up
+    static ref NITRO: Result<Arc<dyn Driver + Send + Sync>, String> = {
+    let started = std::time::Instant::now();
+    init_logging();
+    LazyLock::force(&BOOT_SESSION_ID);
     // Hit the dev driver when the `dev` feature is enabled
     // You can enable this while working locally, ensuring it's disabled when this service is deployed.
     #[cfg(not(feature = "nitro"))]
-    {
+    let driver = {
         use clap::Parser;
-        let args = Args::parse();
-
-        let int_certs = args
-            .int_certs
-            .into_iter()
-            .map(|path| {
-                let der = std::fs::read(&path).unwrap();
-                ByteBuf::from(der)
-            })
-            .collect::<Vec<ByteBuf>>();
+        dev_driver(Args::parse())
+    };
+    #[cfg(feature = "nitro")]
+    let driver: Result<Arc<dyn Driver + Send + Sync>, String> = Ok(Arc::new(Nitro::init()));
+    if let Err(detail) = &driver {
+        log::error!("unable to set up the attestation driver: {detail}");
+    }
+    stats::record_driver_init(started.elapsed());
+    driver
+    };
 
-        let end_cert = {
-            let der = std::fs::read(&args.end_cert).unwrap();
-            ByteBuf::from(der)
-        };
+    /// Root used when the caller does not supply one, resolved once per process
+    static ref DEFAULT_ROOT: Result<Vec<u8>, Error> = load_default_root();
+}
 
-        let signing_key = {
-            let der = std::fs::read(&args.signing_key).unwrap();
-            SecretKey::from_pkcs8_der(&der).unwrap()
-        };
+/// Build the development driver, checking its intermediates chain up from the
+/// end certificate
+#[cfg(not(feature = "nitro"))]
+fn dev_driver(args: Args) -> Result<Arc<dyn Driver + Send + Sync>, String> {
+    let int_cert_paths = if args.int_certs.is_empty() {
+        vec![None]
+    } else {
+        args.int_certs.into_iter().map(Some).collect()
+    };
+    let int_certs = int_cert_paths
+        .into_iter()
+        .map(|path| dev_file!(path, "int-certificate.der"))
+        .collect::<Vec<Vec<u8>>>();
+
+    let end_cert = dev_file!(args.end_cert, "end-certificate.der");
+    check_int_cert_order(&end_cert, &int_certs)?;
 
-        Nitro::from(nsm_nitro_enclave_utils::driver::dev::DevNitro::builder(signing_key, end_cert)
-            // Using `Pcrs::zeros` to get attestation documents similar to how the Nsm module will return all zeros in "debug mode"
-            // https://docs.aws.amazon.com/enclaves/latest/user/getting-started.html#run
-            // `Pcrs` can be generated in another ways too, but some of them require extra feature flags not enabled in this binary.
-            .pcrs(Pcrs::zeros())
-            .ca_bundle(int_certs)
-            .build())
+    // The NSM orders its cabundle the other way round: root first, ending with
+    // the end certificate's issuer
+    let ca_bundle: Vec<ByteBuf> = int_certs.into_iter().rev().map(ByteBuf::from).collect();
+    let mut ca_chain = ca_bundle.clone();
+    if !ca_bundle.first().is_some_and(|cert| is_self_signed(cert)) {
+        match dev_root() {
+            Ok(root) => ca_chain.insert(0, ByteBuf::from(root)),
+            Err(err) => log::warn!("development CA chain has no root: {err}"),
+        }
     }
-    #[cfg(feature = "nitro")]
-    Nitro::init()
+    let _ = DEV_CA_CHAIN.set(ca_chain);
+    // Parsed last and handed straight to the builder, which keeps it only as the
+    // signing key it derives, so no other copy outlives this call (but for the
+    // one re-signing documents with a vendor extension, if asked for)
+    let signing_key = parse_signing_key(&mut dev_file!(args.signing_key, "end-signing-key.der"))?;
+    let vendor_extension = args.vendor_extension.map(|(key, value)| VendorExtension {
+        key,
+        value,
+        signing_key: SigningKey::from(&signing_key),
     });
+    let driver = nsm_nitro_enclave_utils::driver::dev::DevNitro::builder(
+        signing_key,
+        ByteBuf::from(end_cert),
+    )
+    // Using `Pcrs::zeros` to get attestation documents similar to how the Nsm module will return all zeros in "debug mode"
+    // https://docs.aws.amazon.com/enclaves/latest/user/getting-started.html#run
+    // `Pcrs` can be generated in another ways too, but some of them require extra feature flags not enabled in this binary.
+    .pcrs(Pcrs::zeros())
+    .ca_bundle(ca_bundle)
+    .build();
+    Ok(Arc::new(DevDriver {
+        driver,
+        delay: std::time::Duration::from_millis(args.attest_delay_ms.unwrap_or(0)),
+        vendor_extension,
+        unexpected_responses: args.unexpected_responses,
+        describe_digest: args.describe_digest.unwrap_or(NsmDigest::SHA384),
+    }))
+}
+
+/// Parse the development driver's PKCS#8 signing key, then scrub `der` so the key
+/// does not linger in memory once the buffer is freed
+#[cfg(not(feature = "nitro"))]
+fn parse_signing_key(der: &mut [u8]) -> Result<SecretKey, String> {
+    let key = SecretKey::from_pkcs8_der(der)
+        .map_err(|err| format!("signing key is not a PKCS#8 DER key: {err}"));
+    der.zeroize();
+    key
+}
+
+/// Bytes in each `GetRandom` answer of the development driver, as many as the NSM's
+#[cfg(not(feature = "nitro"))]
+const DEV_RANDOM_LEN: usize = 256;
+
+/// The development driver, answering each request only after `delay` (see
+/// `--attest-delay-ms`), and `GetRandom` and `DescribeNSM`, which `DevNitro`
+/// does not implement, from the kernel RNG and a stub description
+#[cfg(not(feature = "nitro"))]
+struct DevDriver<D> {
+    driver: D,
+    delay: std::time::Duration,
+    vendor_extension: Option<VendorExtension>,
+    /// See `--unexpected-responses`
+    unexpected_responses: bool,
+    /// See `--describe-digest`
+    describe_digest: NsmDigest,
+}
+
+/// Field added to every document's payload, see `--vendor-extension`
+#[cfg(not(feature = "nitro"))]
+struct VendorExtension {
+    key: String,
+    value: String,
+    /// Re-signs the extended payload, as the end certificate's key
+    signing_key: SigningKey,
+}
+
+#[cfg(not(feature = "nitro"))]
+impl VendorExtension {
+    /// Add the field to the payload of `document`, signing it again
+    fn extend(&self, document: &[u8]) -> Option<Vec<u8>> {
+        let cose = CoseSign1::from_slice(document).ok()?;
+        let mut payload: ciborium::Value = ciborium::from_reader(cose.payload.as_deref()?).ok()?;
+        payload.as_map_mut()?.push((
+            ciborium::Value::Text(self.key.clone()),
+            ciborium::Value::Text(self.value.clone()),
+        ));
+        let mut extended = vec![];
+        ciborium::into_writer(&payload, &mut extended).ok()?;
+        sign_dev_document(&self.signing_key, extended)
+    }
+}
+
+/// Sign `payload` into a COSE_Sign1 document with ES384, as `DevNitro` does.
+/// ECDSA nonces are derived from the key and message (RFC 6979), so the same
+/// payload always gives the same document.
+#[cfg(not(feature = "nitro"))]
+fn sign_dev_document(signing_key: &SigningKey, payload: Vec<u8>) -> Option<Vec<u8>> {
+    coset::CoseSign1Builder::new()
+        .protected(
+            coset::HeaderBuilder::new()
+                .algorithm(iana::Algorithm::ES384)
+                .build(),
+        )
+        .payload(payload)
+        .create_signature(b"", |tbs| {
+            let signature: Signature = signing_key.sign(tbs);
+            signature.to_bytes().to_vec()
+        })
+        .build()
+        .to_vec()
+        .ok()
+}
+
+#[cfg(not(feature = "nitro"))]
+impl<D: Driver> Driver for DevDriver<D> {
+    fn process_request(&self, request: Request) -> Response {
+        std::thread::sleep(self.delay);
+        if let Request::GetRandom = request {
+            let mut random = vec![0; DEV_RANDOM_LEN];
+            OsRng.fill_bytes(&mut random);
+            return Response::GetRandom { random };
+        }
+        if let Request::DescribeNSM = request {
+            return Response::DescribeNSM {
+                version_major: 1,
+                version_minor: 0,
+                version_patch: 0,
+                module_id: "unsecure-development-attestation-document".to_string(),
+                max_pcrs: verify::MAX_PCRS as u16,
+                locked_pcrs: std::collections::BTreeSet::new(),
+                digest: self.describe_digest,
+            };
+        }
+        if self.unexpected_responses && matches!(request, Request::Attestation { .. }) {
+            return Response::LockPCRs;
+        }
+        match (self.driver.process_request(request), &self.vendor_extension) {
+            (Response::Attestation { document }, Some(extension)) => extension
+                .extend(&document)
+                .map_or(Response::Error(ErrorCode::InternalError), |document| {
+                    Response::Attestation { document }
+                }),
+            (response, _) => response,
+        }
+    }
+}
+
+/// Whether `der` is a certificate signed by its own key, as roots are (names
+/// alone cannot tell, as the test PKI's are empty)
+#[cfg(not(feature = "nitro"))]
+fn is_self_signed(der: &[u8]) -> bool {
+    Certificate::from_der(der).is_ok_and(|cert| verify::verify_issued_by(&cert, &cert).is_ok())
+}
+
+/// Check `end_cert` was issued by the first of `int_certs`, and each of those by
+/// the one after it, so the driver cannot produce documents that fail to verify
+#[cfg(not(feature = "nitro"))]
+fn check_int_cert_order(end_cert: &[u8], int_certs: &[Vec<u8>]) -> Result<(), String> {
+    let parse = |der: &[u8], name: &str| {
+        Certificate::from_der(der).map_err(|err| format!("{name} is not a DER certificate: {err}"))
+    };
+    let mut name = String::from("end certificate");
+    let mut cert = parse(end_cert, &name)?;
+    for (index, der) in int_certs.iter().enumerate() {
+        let issuer_name = format!("intermediate {index}");
+        let issuer = parse(der, &issuer_name)?;
+        verify::verify_issued_by(&cert, &issuer).map_err(|_| {
+            format!(
+                "{name} was not issued by {issuer_name}; --int-certs must start with the \
+                 end certificate's issuer and end nearest the root"
+            )
+        })?;
+        (name, cert) = (issuer_name, issuer);
+    }
+    Ok(())
+}
+
+/// AWS Nitro Enclaves Root-G1, as published at
+/// https://docs.aws.amazon.com/enclaves/latest/user/verify-root.html
+/// (SHA-256 fingerprint 641A0321A3E244EFE456463195D606317ED7CDCC3C1756E09893F3C68F79BB5B)
+const AWS_NITRO_ROOT: &[u8] = include_bytes!("../certs/aws-nitro-enclaves-root-g1.der");
+
+/// Names a DER root certificate file that replaces the embedded AWS root
+const ROOT_CERT_ENV: &str = "DENO_NITRO_ROOT_CERT";
+
+/// Sets the log level (`error`, `warn`, `info`, `debug`, `trace` or `off`)
+const LOG_ENV: &str = "DENO_NITRO_LOG";
+
+/// Default ceilings for [`attest_batch`], see [`set_batch_limits`]
+const DEFAULT_BATCH_MAX_ITEMS: usize = 1024;
+const DEFAULT_BATCH_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+static BATCH_MAX_ITEMS: AtomicUsize = AtomicUsize::new(DEFAULT_BATCH_MAX_ITEMS);
+static BATCH_MAX_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_BATCH_MAX_BYTES);
+
+/// The development driver's CA certificates, root first, see [`dev_ca_chain`]
+#[cfg(not(feature = "nitro"))]
+static DEV_CA_CHAIN: OnceLock<Vec<ByteBuf>> = OnceLock::new();
+
+/// Whether verification also requires every certificate to be within its validity
+/// window, see `strictCertValidity` in [`InitOptions`]
+static STRICT_CERT_VALIDITY: AtomicBool = AtomicBool::new(false);
+
+/// Largest document, root, policy or other input a binding parses, see
+/// `maxInputBytes` in [`InitOptions`]. Attestations are a few KiB, so the default
+/// leaves plenty of room while bounding what a hostile input can make us allocate.
+const DEFAULT_MAX_INPUT_BYTES: usize = 1024 * 1024;
+
+static MAX_INPUT_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_INPUT_BYTES);
+
+/// Whether documents may break the AWS PCR conventions, see `relaxPcrChecks` in
+/// [`InitOptions`]
+static RELAX_PCR_CHECKS: AtomicBool = AtomicBool::new(false);
+
+/// Tolerance for certificate validity windows, for clocks that disagree slightly
+const DEFAULT_CERT_CLOCK_SKEW_MS: u32 = 60_000;
+
+static CERT_CLOCK_SKEW_MS: AtomicU32 = AtomicU32::new(DEFAULT_CERT_CLOCK_SKEW_MS);
+
+/// Random id of this process's session with the driver, see [`boot_session_id`]
+static BOOT_SESSION_ID: LazyLock<[u8; 16]> = LazyLock::new(|| {
+    let mut id = [0; 16];
+    OsRng.fill_bytes(&mut id);
+    id
+});
+
+/// Whether attestations with `user_data` wrap it in a [`BootSessionHeader`]
+static EMBED_BOOT_SESSION_ID: AtomicBool = AtomicBool::new(false);
+
+/// CBOR tag for a typed `Uint8Array` (RFC 8746), which cbor-x may wrap byte strings in
+const TAG_UINT8_ARRAY: u64 = 64;
+
+/// COSE algorithm the driver signs with, once it has been observed
+static SIGNING_ALGORITHM: OnceLock<i32> = OnceLock::new();
+
+thread_local! {
+    /// Status of the last fallible binding called on this thread, see [`last_error`]
+    static LAST_ERROR: Cell<i32> = const { Cell::new(0) };
+    /// Human readable detail for [`LAST_ERROR`], see [`last_error_message`]
+    static LAST_ERROR_MESSAGE: RefCell<String> = const { RefCell::new(String::new()) };
+    /// Request ID set by [`set_request_id`], included in every log line this
+    /// thread emits while it is set
+    static REQUEST_ID: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Everything [`configure`] can set. Omitted options keep their current value.
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct InitOptions {
+    /// See [`set_batch_limits`]
+    batch_max_items: Option<u32>,
+    batch_max_bytes: Option<u32>,
+    /// Overrides `DENO_NITRO_LOG`
+    log_level: Option<String>,
+    /// Most [`attest_async`] requests run at once, capped at
+    /// [`pool::MAX_DEVICE_CONCURRENCY`] (defaults to the number of CPUs)
+    attest_pool_size: Option<u32>,
+    /// How long chain and signature results are cached, or 0 (the default) for no cache
+    verification_cache_ttl_ms: Option<u32>,
+    /// Most results cached at once (defaults to [`cache::DEFAULT_SIZE`])
+    verification_cache_size: Option<u32>,
+    /// Reject documents whose leaf or intermediate certificates are expired or
+    /// not yet valid, even if otherwise valid (off by default)
+    strict_cert_validity: Option<bool>,
+    /// How far certificate validity windows are stretched either side when
+    /// strict, and how far ahead of the clock document timestamps may be in age
+    /// checks (defaults to [`DEFAULT_CERT_CLOCK_SKEW_MS`])
+    cert_clock_skew_ms: Option<u32>,
+    /// Wrap the `user_data` of every attestation that has one in a
+    /// [`BootSessionHeader`] (off by default)
+    embed_boot_session_id: Option<bool>,
+    /// Most [`attest_tagged`] requests a tag can make in a burst, or 0 (the
+    /// default) for no limit
+    rate_limit_capacity: Option<u32>,
+    /// How fast each tag's allowance refills (defaults to
+    /// [`rate_limit::DEFAULT_REFILL_PER_SECOND`])
+    rate_limit_refill_per_second: Option<u32>,
+    /// Accept documents with any number of PCRs of any length, for NSM-compatible
+    /// modules that do not follow the AWS conventions (off by default). Signatures
+    /// and chains are still verified, but this weakens policy: only enable it for
+    /// modules known not to be AWS Nitro.
+    relax_pcr_checks: Option<bool>,
+    /// Most decoded documents kept for reuse by the inspection and verification
+    /// bindings, or 0 (the default) for no cache
+    document_cache_size: Option<u32>,
+    /// Largest input parsed, see [`check_input_size`] (defaults to
+    /// [`DEFAULT_MAX_INPUT_BYTES`])
+    max_input_bytes: Option<u32>,
+    /// Most nonces kept ready for [`attest_with_fresh_nonce`], at most
+    /// [`nonce_pool::MAX_SIZE`], or 0 (the default) to fetch each one from the
+    /// device when needed
+    nonce_pool_size: Option<u32>,
+    /// Most `user_data` bytes attested per window, across every caller, or 0
+    /// (the default) for no quota
+    attest_quota_bytes: Option<u32>,
+    /// Length of the quota's rolling window (defaults to [`quota::DEFAULT_WINDOW`])
+    attest_quota_window_ms: Option<u32>,
+    /// Most nonces [`verify_with_policy`] remembers to reject replays of, or 0
+    /// (the default) to accept a nonce any number of times
+    seen_nonce_capacity: Option<u32>,
+    /// How long accepted nonces are remembered (defaults to
+    /// [`replay::DEFAULT_TTL`])
+    seen_nonce_ttl_ms: Option<u32>,
+}
+
+impl InitOptions {
+    /// Validate every option, then apply them together so a rejected call changes nothing
+    fn apply(self) -> Result<(), Error> {
+        if self.batch_max_items == Some(0) {
+            return Err(Error::InvalidArgument("batchMaxItems must be non-zero"));
+        }
+        if self.batch_max_bytes == Some(0) {
+            return Err(Error::InvalidArgument("batchMaxBytes must be non-zero"));
+        }
+        if self.attest_pool_size == Some(0) {
+            return Err(Error::InvalidArgument("attestPoolSize must be non-zero"));
+        }
+        if self.verification_cache_size == Some(0) {
+            return Err(Error::InvalidArgument(
+                "verificationCacheSize must be non-zero",
+            ));
+        }
+        if self.attest_quota_window_ms == Some(0) {
+            return Err(Error::InvalidArgument(
+                "attestQuotaWindowMs must be non-zero",
+            ));
+        }
+        if self.seen_nonce_ttl_ms == Some(0) {
+            return Err(Error::InvalidArgument("seenNonceTtlMs must be non-zero"));
+        }
+        if self.max_input_bytes == Some(0) {
+            return Err(Error::InvalidArgument("maxInputBytes must be non-zero"));
+        }
+        if self.rate_limit_refill_per_second == Some(0) {
+            return Err(Error::InvalidArgument(
+                "rateLimitRefillPerSecond must be non-zero",
+            ));
+        }
+        if self
+            .nonce_pool_size
+            .is_some_and(|size| size as usize > nonce_pool::MAX_SIZE)
+        {
+            return Err(Error::InvalidArgument("noncePoolSize must be at most 4096"));
+        }
+        let log_level = self
+            .log_level
+            .map(|level| level.parse::<log::LevelFilter>())
+            .transpose()
+            .map_err(|_| {
+                Error::InvalidArgument(
+                    "logLevel must be one of off, error, warn, info, debug or trace",
+                )
+            })?;
+
+        if let Some(max_items) = self.batch_max_items {
+            BATCH_MAX_ITEMS.store(max_items as usize, Ordering::Relaxed);
+        }
+        if let Some(max_bytes) = self.batch_max_bytes {
+            BATCH_MAX_BYTES.store(max_bytes as usize, Ordering::Relaxed);
+        }
+        if let Some(level) = log_level {
+            // Install the logger first so `DENO_NITRO_LOG` cannot override this later
+            init_logging();
+            log::set_max_level(level);
+        }
+        if let Some(size) = self.attest_pool_size {
+            let applied = pool::resize(size as usize);
+            if applied < size as usize {
+                log::warn!("attestPoolSize {size} exceeds the device concurrency, using {applied}");
+            }
+        }
+        if self.verification_cache_ttl_ms.is_some() || self.verification_cache_size.is_some() {
+            cache::configure(
+                self.verification_cache_ttl_ms
+                    .map(|ttl| std::time::Duration::from_millis(ttl.into())),
+                self.verification_cache_size.map(|size| size as usize),
+            );
+        }
+        if let Some(strict) = self.strict_cert_validity {
+            STRICT_CERT_VALIDITY.store(strict, Ordering::Relaxed);
+        }
+        if let Some(skew) = self.cert_clock_skew_ms {
+            CERT_CLOCK_SKEW_MS.store(skew, Ordering::Relaxed);
+        }
+        if let Some(embed) = self.embed_boot_session_id {
+            EMBED_BOOT_SESSION_ID.store(embed, Ordering::Relaxed);
+        }
+        if self.rate_limit_capacity.is_some() || self.rate_limit_refill_per_second.is_some() {
+            rate_limit::configure(self.rate_limit_capacity, self.rate_limit_refill_per_second);
+        }
+        if let Some(relax) = self.relax_pcr_checks {
+            RELAX_PCR_CHECKS.store(relax, Ordering::Relaxed);
+        }
+        if let Some(size) = self.document_cache_size {
+            parsed::configure(size as usize);
+        }
+        if let Some(max_bytes) = self.max_input_bytes {
+            MAX_INPUT_BYTES.store(max_bytes as usize, Ordering::Relaxed);
+        }
+        if let Some(size) = self.nonce_pool_size {
+            nonce_pool::configure(size as usize, get_random);
+        }
+        if self.attest_quota_bytes.is_some() || self.attest_quota_window_ms.is_some() {
+            quota::configure(
+                self.attest_quota_bytes.map(u64::from),
+                self.attest_quota_window_ms
+                    .map(|window| std::time::Duration::from_millis(window.into())),
+            );
+        }
+        if self.seen_nonce_capacity.is_some() || self.seen_nonce_ttl_ms.is_some() {
+            replay::configure(
+                self.seen_nonce_capacity.map(|capacity| capacity as usize),
+                self.seen_nonce_ttl_ms
+                    .map(|ttl| std::time::Duration::from_millis(ttl.into())),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The options currently in effect
+    fn current() -> Self {
+        init_logging();
+        Self {
+            batch_max_items: Some(BATCH_MAX_ITEMS.load(Ordering::Relaxed) as u32),
+            batch_max_bytes: Some(BATCH_MAX_BYTES.load(Ordering::Relaxed) as u32),
+            log_level: Some(log::max_level().as_str().to_lowercase()),
+            attest_pool_size: Some(pool::size() as u32),
+            verification_cache_ttl_ms: Some(cache::ttl().as_millis() as u32),
+            verification_cache_size: Some(cache::size() as u32),
+            strict_cert_validity: Some(STRICT_CERT_VALIDITY.load(Ordering::Relaxed)),
+            cert_clock_skew_ms: Some(CERT_CLOCK_SKEW_MS.load(Ordering::Relaxed)),
+            embed_boot_session_id: Some(EMBED_BOOT_SESSION_ID.load(Ordering::Relaxed)),
+            rate_limit_capacity: Some(rate_limit::capacity()),
+            rate_limit_refill_per_second: Some(rate_limit::refill_per_second()),
+            relax_pcr_checks: Some(RELAX_PCR_CHECKS.load(Ordering::Relaxed)),
+            document_cache_size: Some(parsed::size() as u32),
+            max_input_bytes: Some(MAX_INPUT_BYTES.load(Ordering::Relaxed) as u32),
+            nonce_pool_size: Some(nonce_pool::size() as u32),
+            attest_quota_bytes: Some(quota::limit() as u32),
+            attest_quota_window_ms: Some(quota::window().as_millis() as u32),
+            seen_nonce_capacity: Some(replay::capacity() as u32),
+            seen_nonce_ttl_ms: Some(replay::ttl().as_millis() as u32),
+        }
+    }
+}
+
+/// Failures reported across the FFI boundary as non-zero status codes.
+/// Keep the codes in sync with `ErrorCode` in `mod.ts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Error {
+    /// The input is not a COSE_Sign1 attestation document
+    MalformedDocument(&'static str),
+    /// The trusted root certificate could not be loaded or parsed
+    InvalidRoot,
+    /// The chain does not start from the trusted root: the topmost certificate
+    /// of the cabundle (or the leaf, without one) was not issued by it
+    ChainInvalid,
+    /// The document was not signed by its leaf certificate
+    SignatureInvalid,
+    /// An argument was not in the expected format or range
+    InvalidArgument(&'static str),
+    /// A batch exceeded the configured item count or byte ceiling
+    BatchTooLarge,
+    /// The document verified but does not satisfy the verifier's policy
+    PolicyViolation(&'static str),
+    /// The driver did not produce an attestation document
+    AttestationFailed,
+    /// Strict validity is on and a certificate in the chain has expired
+    CertExpired,
+    /// Strict validity is on and a certificate in the chain is not valid yet
+    CertNotYetValid,
+    /// The attestation driver could not be set up
+    DriverUnavailable,
+    /// The caller's tag has used up its attestation allowance for now
+    RateLimited,
+    /// The cabundle has a gap: a certificate below the topmost was not issued
+    /// by the one above it
+    ChainBroken,
+    /// The request was cancelled by [`close_driver`]
+    Cancelled,
+    /// An input was larger than `maxInputBytes` (see [`InitOptions`]) allows
+    InputTooLarge,
+    /// The attestation's `user_data` does not fit in what is left of the byte
+    /// quota for the current window
+    QuotaExceeded,
+    /// The driver answered with a response of another kind than the request,
+    /// e.g. from firmware speaking a newer NSM protocol
+    UnexpectedResponse,
+    /// A frame from [`attest_framed`] is truncated, or fails its checksum
+    FrameCorrupt,
+    /// The document's nonce was already accepted, with `seenNonceCapacity` set
+    NonceReplayed,
+    /// Documents declare a different digest from the one the NSM reports to
+    /// `DescribeNSM`, see [`verify_digest_consistency`]
+    DigestMismatch,
+    /// The document's PCRs break the AWS Nitro conventions. Reported with a code
+    /// for each kind of anomaly by [`validate_pcr_banks`], and as
+    /// `MalformedDocument` by everything else (see [`Error::generalize`]).
+    PcrAnomaly(verify::PcrAnomaly),
+}
+
+impl Error {
+    fn code(&self) -> i32 {
+        match self {
+            Error::MalformedDocument(_) => 1,
+            Error::InvalidRoot => 2,
+            Error::ChainInvalid => 3,
+            Error::SignatureInvalid => 4,
+            Error::InvalidArgument(_) => 5,
+            Error::BatchTooLarge => 6,
+            Error::PolicyViolation(_) => 7,
+            Error::AttestationFailed => 8,
+            Error::CertExpired => 9,
+            Error::CertNotYetValid => 10,
+            Error::DriverUnavailable => 11,
+            Error::RateLimited => 12,
+            Error::ChainBroken => 13,
+            Error::Cancelled => 14,
+            Error::InputTooLarge => 15,
+            Error::QuotaExceeded => 16,
+            Error::UnexpectedResponse => 17,
+            Error::FrameCorrupt => 18,
+            Error::NonceReplayed => 19,
+            Error::DigestMismatch => 20,
+            Error::PcrAnomaly(verify::PcrAnomaly::Count) => 21,
+            Error::PcrAnomaly(verify::PcrAnomaly::IndexOutOfRange) => 22,
+            Error::PcrAnomaly(verify::PcrAnomaly::DuplicateIndex) => 23,
+            Error::PcrAnomaly(verify::PcrAnomaly::LengthMismatch) => 24,
+        }
+    }
+
+    /// A [`PcrAnomaly`](Error::PcrAnomaly) as the `MalformedDocument` it is to
+    /// callers not asking which kind it is
+    fn generalize(self) -> Error {
+        match self {
+            Error::PcrAnomaly(anomaly) => Error::MalformedDocument(anomaly.reason()),
+            err => err,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MalformedDocument(reason) => write!(f, "malformed document: {reason}"),
+            Error::InvalidRoot => write!(f, "invalid root certificate"),
+            Error::ChainInvalid => write!(f, "certificate chain does not verify"),
+            Error::SignatureInvalid => write!(f, "document signature does not verify"),
+            Error::InvalidArgument(reason) => write!(f, "invalid argument: {reason}"),
+            Error::BatchTooLarge => write!(f, "batch exceeds the configured limits"),
+            Error::PolicyViolation(reason) => write!(f, "policy violation: {reason}"),
+            Error::AttestationFailed => write!(f, "the driver did not produce an attestation"),
+            Error::CertExpired => write!(f, "a certificate in the chain has expired"),
+            Error::CertNotYetValid => write!(f, "a certificate in the chain is not yet valid"),
+            Error::DriverUnavailable => write!(f, "the attestation driver could not be set up"),
+            Error::RateLimited => write!(f, "attestation rate limit reached for this tag"),
+            Error::ChainBroken => write!(f, "certificate chain does not link up to the leaf"),
+            Error::Cancelled => write!(f, "the driver was closed"),
+            Error::InputTooLarge => write!(f, "input exceeds the configured maxInputBytes"),
+            Error::QuotaExceeded => write!(f, "attested byte quota used up for this window"),
+            Error::UnexpectedResponse => {
+                write!(f, "the driver answered with an unexpected response")
+            }
+            Error::FrameCorrupt => write!(f, "frame is truncated or fails its checksum"),
+            Error::NonceReplayed => write!(f, "nonce has already been accepted"),
+            Error::DigestMismatch => {
+                write!(
+                    f,
+                    "documents declare a different digest than the NSM reports"
+                )
+            }
+            Error::PcrAnomaly(anomaly) => write!(f, "malformed document: {}", anomaly.reason()),
+        }
+    }
+}
+
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            REQUEST_ID.with_borrow(|id| {
+                if id.is_empty() {
+                    eprintln!("[deno_nitro {}] {}", record.level(), record.args());
+                } else {
+                    eprintln!("[deno_nitro {} {id}] {}", record.level(), record.args());
+                }
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn init_logging() {
+    if log::set_logger(&LOGGER).is_ok() {
+        let level = std::env::var(LOG_ENV)
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(log::LevelFilter::Warn);
+        log::set_max_level(level);
+    }
+}
+
+/// Tag every log line this thread emits from now on with `request_id`, so the
+/// lines of one request can be picked out among many (e.g. to correlate them
+/// with a distributed trace), until it is cleared by an empty `request_id`.
+/// Bindings run on the calling thread, so setting it around a call covers
+/// everything logged while processing it, including on the threads the call
+/// hands work to. Returns 0.
+#[deno_bindgen]
+fn set_request_id(request_id: &str) -> i32 {
+    replace_request_id(request_id.to_string());
+    0
+}
+
+/// This thread's request ID (see [`set_request_id`]), for a thread doing work on
+/// its behalf to [`replace_request_id`] with
+fn request_id() -> String {
+    REQUEST_ID.with_borrow(String::clone)
+}
+
+/// Tag this thread's log lines with `request_id`, returning the ID it replaces
+fn replace_request_id(request_id: String) -> String {
+    REQUEST_ID.replace(request_id)
 }
 
 #[deno_bindgen]
 fn attest(bytes: &[u8]) -> Vec<u8> {
     let buf = ByteBuf::from(bytes);
-    let attestation = r_attest(buf);
+    report_attestation(r_attest(buf))
+}
+
+/// Like [`attest`], on behalf of the caller named by `tag`, which must have a
+/// token left in its bucket (see `rateLimitCapacity` in [`InitOptions`]) so that
+/// one tenant cannot monopolise the NSM. Returns an empty buffer with
+/// [`last_error`] set if the tag is rate limited or the attestation failed.
+#[deno_bindgen]
+fn attest_tagged(tag: &str, bytes: &[u8]) -> Vec<u8> {
+    if !rate_limit::try_take(tag) {
+        log::debug!("attestation for tag {tag:?} rate limited");
+        let err = Error::RateLimited;
+        set_last_error(err.code(), err.to_string());
+        return vec![];
+    }
+    report_attestation(r_attest(ByteBuf::from(bytes)))
+}
+
+/// Record the outcome of an attestation in [`last_error`], returning the
+/// document, or an empty buffer if there is none
+fn report_attestation(attestation: Result<Vec<u8>, Error>) -> Vec<u8> {
+    match attestation {
+        Ok(document) => {
+            set_last_error(0, String::new());
+            document
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+fn r_attest(bytes: ByteBuf) -> Result<Vec<u8>, Error> {
+    r_attest_with(Some(bytes), None, None)
+}
+
+/// Ask the driver for an attestation, once the caller's `user_data` fits in the
+/// [`quota`] (see `attestQuotaBytes` in [`InitOptions`]). Only attestations the
+/// driver makes use the quota up.
+fn r_attest_with(
+    user_data: Option<ByteBuf>,
+    public_key: Option<ByteBuf>,
+    nonce: Option<ByteBuf>,
+) -> Result<Vec<u8>, Error> {
+    let Some(charge) = quota::try_take(user_data.as_ref().map_or(0, |data| data.len())) else {
+        log::debug!("attestation refused, the byte quota is used up");
+        return Err(Error::QuotaExceeded);
+    };
+    let attestation = request_attestation(user_data, public_key, nonce);
+    if attestation.is_err() {
+        quota::refund(charge);
+    }
     attestation
 }
 
-fn r_attest(bytes: ByteBuf) -> Vec<u8> {
-    let response = NITRO.process_request(Request::Attestation {
-        user_data: bytes.into(),
-        public_key: None,
-        nonce: None,
+fn request_attestation(
+    user_data: Option<ByteBuf>,
+    public_key: Option<ByteBuf>,
+    nonce: Option<ByteBuf>,
+) -> Result<Vec<u8>, Error> {
+    let started = std::time::Instant::now();
+    let Ok(driver) = NITRO.as_ref() else {
+        return Err(Error::AttestationFailed);
+    };
+    // Attestations without user_data (e.g. of a key alone) are left without one
+    let user_data = match user_data {
+        Some(user_data) if EMBED_BOOT_SESSION_ID.load(Ordering::Relaxed) => {
+            Some(ByteBuf::from(serialize::to_vec(&BootSessionHeader {
+                boot_session_id: ByteBuf::from(BOOT_SESSION_ID.to_vec()),
+                user_data,
+            })))
+        }
+        user_data => user_data,
+    };
+    let response = driver.process_request(Request::Attestation {
+        user_data,
+        public_key,
+        nonce,
     });
-    if let Response::Attestation { document } = response {
-        document
+    let document = match response {
+        Response::Attestation { document } => Ok(document),
+        Response::Error(code) => {
+            log::warn!("the driver failed to attest: {code:?}");
+            Err(Error::AttestationFailed)
+        }
+        response => {
+            log::error!(
+                "the driver answered an attestation request with {} ({:?})",
+                response_kind(&response),
+                std::mem::discriminant(&response)
+            );
+            Err(Error::UnexpectedResponse)
+        }
+    };
+    stats::record(
+        started.elapsed(),
+        document.as_ref().is_ok_and(|document| !document.is_empty()),
+    );
+    if let Ok(document) = &document
+        && !document.is_empty()
+    {
+        log::debug!(
+            "attested in {:?}: {} byte document",
+            started.elapsed(),
+            document.len()
+        );
+    }
+    match document {
+        Ok(document) if document.is_empty() => Err(Error::AttestationFailed),
+        document => document,
+    }
+}
+
+/// Name of the kind of `response`, for logging it without its contents
+fn response_kind(response: &Response) -> &'static str {
+    match response {
+        Response::DescribePCR { .. } => "DescribePCR",
+        Response::ExtendPCR { .. } => "ExtendPCR",
+        Response::LockPCR => "LockPCR",
+        Response::LockPCRs => "LockPCRs",
+        Response::DescribeNSM { .. } => "DescribeNSM",
+        Response::Attestation { .. } => "Attestation",
+        Response::GetRandom { .. } => "GetRandom",
+        Response::Error(_) => "Error",
+        _ => "an unknown response",
+    }
+}
+
+/// `user_data` of attestations made with `embedBootSessionId` set (see
+/// [`InitOptions`]): a CBOR map of the [`boot_session_id`] and the caller's own data
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct BootSessionHeader {
+    boot_session_id: ByteBuf,
+    user_data: ByteBuf,
+}
+
+/// 16 random bytes generated when the library first sets up the driver, so they
+/// stay the same for as long as this process (the running enclave) does. Two
+/// documents carrying the same id came from the same enclave instance, without
+/// a restart in between.
+#[deno_bindgen]
+fn boot_session_id() -> Vec<u8> {
+    BOOT_SESSION_ID.to_vec()
+}
+
+/// The boot session id embedded in `document`'s `user_data` (see
+/// [`BootSessionHeader`]), or an empty buffer if it has none. Like
+/// [`document_is_fresh`] this does not verify the document; an empty buffer
+/// with [`last_error`] set means `document` could not be decoded.
+#[deno_bindgen]
+fn document_boot_session_id(document: &[u8]) -> Vec<u8> {
+    match decode_document(document) {
+        Ok((_, doc)) => {
+            set_last_error(0, String::new());
+            embedded_boot_session_id(&doc).map_or_else(Vec::new, ByteBuf::into_vec)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+/// The boot session id in `doc`'s [`BootSessionHeader`], if it has one
+fn embedded_boot_session_id(doc: &AttestationDoc) -> Option<ByteBuf> {
+    doc.user_data
+        .as_ref()
+        .and_then(|data| serialize::from_slice::<BootSessionHeader>(data).ok())
+        .map(|header| header.boot_session_id)
+}
+
+/// Whether `doc_a` and `doc_b` came from the same running enclave instance,
+/// returning 1 if they share a `module_id` and an embedded [`boot_session_id`],
+/// and 0 otherwise, so a restart between two handshakes shows up as 0.
+///
+/// When neither document embeds a boot session id only `module_id` is compared,
+/// which is weaker: `module_id` names the enclave, not the process, so a process
+/// restarted inside the same enclave goes unnoticed (and the development
+/// driver's `module_id` never changes). A document with an id and one without
+/// give 0. Neither document is verified, so check both with
+/// [`verify_attestation`] first; one that cannot be decoded also gives 0 but sets
+/// [`last_error`].
+#[deno_bindgen]
+fn same_instance(doc_a: &[u8], doc_b: &[u8]) -> u8 {
+    match r_same_instance(doc_a, doc_b) {
+        Ok(same) => {
+            set_last_error(0, String::new());
+            u8::from(same)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            0
+        }
+    }
+}
+
+fn r_same_instance(doc_a: &[u8], doc_b: &[u8]) -> Result<bool, Error> {
+    let (_, a) = decode_document(doc_a)?;
+    let (_, b) = decode_document(doc_b)?;
+    Ok(a.module_id == b.module_id && embedded_boot_session_id(&a) == embedded_boot_session_id(&b))
+}
+
+/// Like [`attest`], but resolves as a promise on the JS side, running the request
+/// on another thread once the attestation pool (see `attestPoolSize` in
+/// [`InitOptions`]) has room for it. The pool thread's [`last_error`] is not the
+/// caller's, so the result carries its own status: a 4 byte big-endian 0 then
+/// the document, or an error code then why there is none, in UTF-8. The code is
+/// `Cancelled` once [`close_driver`] has been called. Runs on a thread of its
+/// own, so it is tagged with `request_id` here rather than by [`set_request_id`].
+#[deno_bindgen(non_blocking)]
+fn attest_async(request_id: &str, bytes: &[u8]) -> Vec<u8> {
+    let buf = ByteBuf::from(bytes);
+    let previous = replace_request_id(request_id.to_string());
+    let outcome = pool::run(move || r_attest(buf)).and_then(|result| result);
+    let (status, body) = match outcome {
+        Ok(document) => (0, document),
+        Err(err) => {
+            log::debug!("attest_async: {err}");
+            (err.code(), err.to_string().into_bytes())
+        }
+    };
+    replace_request_id(previous);
+    let mut result = Vec::with_capacity(4 + body.len());
+    result.extend_from_slice(&status.to_be_bytes());
+    result.extend_from_slice(&body);
+    result
+}
+
+/// Shut down [`attest_async`]: requests waiting for the pool, or for the device,
+/// fail at once with `Cancelled`, and any made later do too, for the rest of the
+/// process. A request the device was already handling is left to finish on its
+/// own, and its document dropped. Synchronous bindings are unaffected.
+/// Returns 0; closing again does nothing.
+#[deno_bindgen]
+fn close_driver() -> i32 {
+    pool::close();
+    0
+}
+
+/// An attestation binding the enclave's key, and that key's signature over a challenge
+#[derive(serde::Serialize)]
+struct SignedChallenge {
+    attestation: ByteBuf,
+    /// ECDSA P-384 / SHA-384, as the 96 byte `r || s` (IEEE P1363) form WebCrypto uses
+    signature: ByteBuf,
+}
+
+/// Attest `user_data` with the [`bound_key`] as `public_key` and `challenge` as the
+/// `nonce`, and sign the challenge with that key, so a relying party can check in
+/// one exchange that the document is fresh and that the enclave holds the key.
+/// Returns a CBOR `{ attestation, signature }` map (see [`SignedChallenge`]), or an
+/// empty buffer with [`last_error`] set if the attestation failed.
+#[deno_bindgen]
+fn attest_and_sign(challenge: &[u8], user_data: &[u8]) -> Vec<u8> {
+    let attestation = report_attestation(r_attest_with(
+        Some(ByteBuf::from(user_data)),
+        Some(ByteBuf::from(bound_key::public_key_der())),
+        Some(ByteBuf::from(challenge)),
+    ));
+    if attestation.is_empty() {
+        return vec![];
+    }
+
+    let signature = bound_key::sign_challenge(challenge);
+    serialize::to_vec(&SignedChallenge {
+        attestation: ByteBuf::from(attestation),
+        signature: ByteBuf::from(signature.to_bytes().to_vec()),
+    })
+}
+
+/// Attest `public_key`, a DER SubjectPublicKeyInfo (e.g. of a TLS key), with
+/// `nonce` if it is not empty and no `user_data` at all, for flows where the key
+/// is the only thing being vouched for. Returns an empty buffer with
+/// [`last_error`] set if the key is not an SPKI or the attestation failed.
+#[deno_bindgen]
+fn attest_key(public_key: &[u8], nonce: &[u8]) -> Vec<u8> {
+    if SubjectPublicKeyInfoRef::from_der(public_key).is_err() {
+        let err = Error::InvalidArgument("public key is not a DER SubjectPublicKeyInfo");
+        set_last_error(err.code(), err.to_string());
+        return vec![];
+    }
+    let nonce = (!nonce.is_empty()).then(|| ByteBuf::from(nonce));
+    report_attestation(r_attest_with(None, Some(ByteBuf::from(public_key)), nonce))
+}
+
+/// Attest the SHA-256 of `payload` as the `user_data`, rather than the payload
+/// itself, so payloads of any size can be vouched for within the NSM's limit on
+/// `user_data`. The relying party, given the payload alongside the document,
+/// checks it with [`verify_user_data_hash`]. Returns an empty buffer with
+/// [`last_error`] set if the attestation failed.
+#[deno_bindgen]
+fn attest_hashed(payload: &[u8]) -> Vec<u8> {
+    report_attestation(r_attest(ByteBuf::from(Sha256::digest(payload).to_vec())))
+}
+
+/// Attest the SHA-384 of `cert_der`, a DER X.509 certificate (e.g. the server
+/// certificate of a TLS endpoint), as the `user_data`, with `nonce` if it is not
+/// empty, so the document commits to that exact certificate for channel binding.
+/// The relying party checks the certificate it was presented with
+/// [`verify_tls_cert_binding`]. Returns an empty buffer with [`last_error`] set if
+/// `cert_der` is not a certificate or the attestation failed.
+#[deno_bindgen]
+fn attest_tls_cert(cert_der: &[u8], nonce: &[u8]) -> Vec<u8> {
+    if Certificate::from_der(cert_der).is_err() {
+        let err = Error::InvalidArgument("certificate is not a DER X.509 certificate");
+        set_last_error(err.code(), err.to_string());
+        return vec![];
+    }
+    let nonce = (!nonce.is_empty()).then(|| ByteBuf::from(nonce));
+    let digest = ByteBuf::from(Sha384::digest(cert_der).to_vec());
+    report_attestation(r_attest_with(Some(digest), None, nonce))
+}
+
+/// Whether [`serve_socket`] is listening, so only one listener is ever started
+#[cfg(feature = "socket")]
+static SERVING_SOCKET: AtomicBool = AtomicBool::new(false);
+
+/// Serve attestations to other processes on this host, such as components that
+/// cannot load these bindings, over a Unix domain socket at `path` (see
+/// [`socket`] for the protocol). Requests go through the same driver, quota and
+/// statistics as [`attest`]. A socket already at `path`, e.g. left by an earlier
+/// process, is replaced. Returns 0 once listening, or sets [`last_error`] and
+/// returns `InvalidArgument` if already listening or the socket cannot be bound.
+/// Only built with the `socket` feature.
+#[cfg(feature = "socket")]
+#[deno_bindgen]
+fn serve_socket(path: &str) -> i32 {
+    match r_serve_socket(std::path::Path::new(path)) {
+        Ok(()) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+#[cfg(feature = "socket")]
+fn r_serve_socket(path: &std::path::Path) -> Result<(), Error> {
+    if SERVING_SOCKET.swap(true, Ordering::Relaxed) {
+        return Err(Error::InvalidArgument("already serving on a socket"));
+    }
+    init_logging();
+    socket::serve(path, attest_socket_request).map_err(|err| {
+        SERVING_SOCKET.store(false, Ordering::Relaxed);
+        log::error!("unable to listen on {}: {err}", path.display());
+        Error::InvalidArgument("socket could not be bound")
+    })?;
+    log::info!("serving attestations on {}", path.display());
+    Ok(())
+}
+
+/// Attest a [`socket::AttestRequest`] received by [`serve_socket`]
+#[cfg(feature = "socket")]
+fn attest_socket_request(request: &[u8]) -> Result<Vec<u8>, Error> {
+    let request: socket::AttestRequest = serialize::from_slice(request)
+        .map_err(|_| Error::InvalidArgument("request is not a valid CBOR map"))?;
+    let previous = replace_request_id(request.request_id.unwrap_or_default());
+    let attestation = r_attest_with(request.user_data, request.public_key, request.nonce);
+    replace_request_id(previous);
+    attestation
+}
+
+/// Like [`attest`], with a fresh random nonce drawn from the NSM (`GetRandom`),
+/// which a relying party can read back from the document's `nonce`. Nonces come
+/// from the [`nonce_pool`] when `noncePoolSize` (see [`InitOptions`]) is set, so
+/// the device is not asked on the critical path, and are never used twice.
+/// Returns an empty buffer with [`last_error`] set if the driver could not
+/// provide a nonce or attest.
+#[deno_bindgen]
+fn attest_with_fresh_nonce(bytes: &[u8]) -> Vec<u8> {
+    let Some(nonce) = nonce_pool::take(get_random) else {
+        let err = Error::AttestationFailed;
+        set_last_error(err.code(), format!("{err}: no random nonce"));
+        return vec![];
+    };
+    report_attestation(r_attest_with(
+        Some(ByteBuf::from(bytes)),
+        None,
+        Some(ByteBuf::from(nonce.to_vec())),
+    ))
+}
+
+/// Random bytes from the driver's `GetRandom`, or `None` if it has none to give
+fn get_random() -> Option<Vec<u8>> {
+    let driver = NITRO.as_ref().ok()?;
+    match driver.process_request(Request::GetRandom) {
+        Response::GetRandom { random } if !random.is_empty() => Some(random),
+        _ => None,
+    }
+}
+
+/// Most bytes [`decompress_document`] inflates to: far more than any NSM
+/// document, whose user data, nonce and public key are at most 1 KiB each
+const MAX_DECOMPRESSED_LEN: usize = 64 * 1024;
+
+/// Like [`attest`], with the document compressed as raw DEFLATE (RFC 1951, no
+/// zlib or gzip header) at the highest level, for links where its cabundle makes
+/// it costly to ship. The result is not a document: it must go through
+/// [`decompress_document`] before anything can verify or inspect it. Returns an
+/// empty buffer with [`last_error`] set if the attestation failed.
+#[deno_bindgen]
+fn attest_compressed(bytes: &[u8]) -> Vec<u8> {
+    let attestation = report_attestation(r_attest(ByteBuf::from(bytes)));
+    if attestation.is_empty() {
+        return vec![];
+    }
+    miniz_oxide::deflate::compress_to_vec(&attestation, 10)
+}
+
+/// Inflate a document from [`attest_compressed`] back to the exact bytes the
+/// driver produced. Returns an empty buffer with [`last_error`] set if `compressed`
+/// is not raw DEFLATE, or inflates to more than [`MAX_DECOMPRESSED_LEN`] bytes.
+#[deno_bindgen]
+fn decompress_document(compressed: &[u8]) -> Vec<u8> {
+    match miniz_oxide::inflate::decompress_to_vec_with_limit(compressed, MAX_DECOMPRESSED_LEN) {
+        Ok(document) => {
+            set_last_error(0, String::new());
+            document
+        }
+        Err(err) => {
+            let err = match err.status {
+                miniz_oxide::inflate::TINFLStatus::HasMoreOutput => {
+                    Error::MalformedDocument("decompressed document too large")
+                }
+                _ => Error::MalformedDocument("not a DEFLATE-compressed document"),
+            };
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+/// Set in a frame's length prefix when a [`FRAME_CHECKSUM_LEN`] byte checksum
+/// follows the document
+const FRAME_HAS_CHECKSUM: u32 = 1 << 31;
+
+/// Bytes of the SHA-256 kept as a frame's checksum
+const FRAME_CHECKSUM_LEN: usize = 8;
+
+/// Checksum of a frame's length prefix and document: a truncated SHA-256, which
+/// catches corruption a CRC would, and more
+fn frame_checksum(prefix: &[u8], document: &[u8]) -> [u8; FRAME_CHECKSUM_LEN] {
+    let digest = Sha256::new()
+        .chain_update(prefix)
+        .chain_update(document)
+        .finalize();
+    let mut checksum = [0; FRAME_CHECKSUM_LEN];
+    checksum.copy_from_slice(&digest[..FRAME_CHECKSUM_LEN]);
+    checksum
+}
+
+/// Like [`attest`], with the document framed for streams that carry one
+/// attestation after another: a 4 byte big-endian length, the document, then,
+/// if `checksum` is not 0, a [`FRAME_CHECKSUM_LEN`] byte trailer over both (see
+/// [`frame_checksum`]) so consumers on lossy transports catch a corrupt frame
+/// before parsing it. The top bit of the length says whether there is a
+/// trailer. Unframe with [`deframe`]. Returns an empty buffer with
+/// [`last_error`] set if the attestation failed.
+#[deno_bindgen]
+fn attest_framed(bytes: &[u8], checksum: u8) -> Vec<u8> {
+    let attestation = report_attestation(r_attest(ByteBuf::from(bytes)));
+    if attestation.is_empty() {
+        return vec![];
+    }
+    let mut prefix = attestation.len() as u32;
+    if checksum != 0 {
+        prefix |= FRAME_HAS_CHECKSUM;
+    }
+    let prefix = prefix.to_be_bytes();
+    let mut frame = Vec::with_capacity(prefix.len() + attestation.len() + FRAME_CHECKSUM_LEN);
+    frame.extend_from_slice(&prefix);
+    frame.extend_from_slice(&attestation);
+    if checksum != 0 {
+        frame.extend_from_slice(&frame_checksum(&prefix, &attestation));
+    }
+    frame
+}
+
+/// The document in a frame from [`attest_framed`], once its trailer, if it has
+/// one, checks out. Returns an empty buffer with [`last_error`] set to
+/// `FrameCorrupt` if the frame is shorter or longer than its prefix says, or
+/// fails its checksum.
+#[deno_bindgen]
+fn deframe(frame: &[u8]) -> Vec<u8> {
+    match r_deframe(frame) {
+        Ok(document) => {
+            set_last_error(0, String::new());
+            document.to_vec()
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+fn r_deframe(frame: &[u8]) -> Result<&[u8], Error> {
+    let (prefix, rest) = frame.split_first_chunk::<4>().ok_or(Error::FrameCorrupt)?;
+    let prefix_value = u32::from_be_bytes(*prefix);
+    let len = (prefix_value & !FRAME_HAS_CHECKSUM) as usize;
+    let trailer_len = if prefix_value & FRAME_HAS_CHECKSUM != 0 {
+        FRAME_CHECKSUM_LEN
     } else {
-        vec![]
+        0
+    };
+    if rest.len() != len + trailer_len {
+        return Err(Error::FrameCorrupt);
+    }
+    let (document, trailer) = rest.split_at(len);
+    if trailer_len != 0 && !bool::from(trailer.ct_eq(&frame_checksum(prefix, document))) {
+        return Err(Error::FrameCorrupt);
+    }
+    Ok(document)
+}
+
+/// Attestation counts and latency percentiles since the library was loaded, as a
+/// CBOR map: `{ attestations, failures, peakAsyncAttestations,
+/// verificationCacheHits, documentCacheHits, driverInitMicros, pooledNonces,
+/// latencyMicros: { p50, p95, p99, max } }`
+#[deno_bindgen]
+fn stats() -> Vec<u8> {
+    serialize::to_vec(&stats::current())
+}
+
+/// Attest every item of `items`, a CBOR array of byte strings, each becoming the
+/// `user_data` of one attestation. Returns a CBOR array holding a document per item
+/// (empty where that attestation failed), or an empty buffer with [`last_error`] set
+/// when the batch is malformed or over the [`set_batch_limits`] ceilings.
+#[deno_bindgen]
+fn attest_batch(items: &[u8]) -> Vec<u8> {
+    match r_attest_batch(items) {
+        Ok(documents) => {
+            set_last_error(0, String::new());
+            documents
+        }
+        Err(err) => {
+            log::warn!("attestation batch rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+/// Set the largest item count and total bytes [`attest_batch`] (of `user_data`)
+/// and [`verify_batch`] (of documents) accept.
+/// Returns 0, or the [`Error::InvalidArgument`] code if either limit is zero.
+#[deno_bindgen]
+fn set_batch_limits(max_items: u32, max_bytes: u32) -> i32 {
+    if max_items == 0 || max_bytes == 0 {
+        return Error::InvalidArgument("batch limits must be non-zero").code();
+    }
+    BATCH_MAX_ITEMS.store(max_items as usize, Ordering::Relaxed);
+    BATCH_MAX_BYTES.store(max_bytes as usize, Ordering::Relaxed);
+    0
+}
+
+/// Apply `options`, a CBOR [`InitOptions`] map, all at once. Meant to be called
+/// at startup ahead of [`init_driver`]. Returns 0, or the [`Error::InvalidArgument`]
+/// code with the first invalid option described by [`last_error_message`], in
+/// which case no option changes.
+#[deno_bindgen]
+fn configure(options: &[u8]) -> i32 {
+    let applied = match serialize::from_slice::<InitOptions>(options) {
+        Ok(options) => options.apply(),
+        Err(err) => {
+            log::warn!("configuration rejected: invalid options: {err}");
+            let code = Error::InvalidArgument("invalid options").code();
+            set_last_error(code, format!("invalid options: {err}"));
+            return code;
+        }
+    };
+    match applied {
+        Ok(()) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            log::warn!("configuration rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+/// The options currently in effect, as a CBOR [`InitOptions`] map with every field set
+#[deno_bindgen]
+fn current_options() -> Vec<u8> {
+    serialize::to_vec(&InitOptions::current())
+}
+
+/// Connect to the driver now instead of on the first request, so that set-up
+/// happens (and fails) at startup. Returns 0, or the [`Error`] code with
+/// [`last_error_message`] saying what went wrong; the outcome is decided
+/// by the first call, and later calls repeat it.
+#[deno_bindgen]
+fn init_driver() -> i32 {
+    match NITRO.as_ref() {
+        Ok(_) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(detail) => {
+            let err = Error::DriverUnavailable;
+            set_last_error(err.code(), format!("{err}: {detail}"));
+            err.code()
+        }
+    }
+}
+
+/// `user_data` of the attestation made by [`self_check`]
+const SELF_CHECK_USER_DATA: &[u8] = b"deno-nitro self check";
+
+/// Run the whole pipeline once at startup: attest a canary `user_data`, then
+/// parse and verify the document, against the development CA with the dev
+/// driver and the default root (see [`load_default_root`]) otherwise. Returns 0
+/// only if every step worked, otherwise the [`Error`] code of the first that
+/// failed, with [`last_error_message`] saying why. The canary counts towards
+/// [`stats`] like any other attestation.
+#[deno_bindgen]
+fn self_check() -> i32 {
+    let code = init_driver();
+    if code != 0 {
+        return code;
+    }
+    match r_self_check() {
+        Ok(()) => {
+            log::info!("self check passed");
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            log::error!("self check failed: {err}");
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+fn r_self_check() -> Result<(), Error> {
+    let document = r_attest(ByteBuf::from(SELF_CHECK_USER_DATA))?;
+    #[cfg(feature = "nitro")]
+    let root = resolve_root(&[])?.to_vec();
+    #[cfg(not(feature = "nitro"))]
+    let root = dev_root()?;
+    r_verify(&document, &root).map(drop)
+}
+
+/// `user_data` of the attestation made by [`verify_digest_consistency`]
+const DIGEST_CHECK_USER_DATA: &[u8] = b"deno-nitro digest consistency check";
+
+/// Check the driver is consistent about its PCR digest, for operators chasing
+/// firmware or driver mismatches: ask it for the digest it reports to
+/// `DescribeNSM`, then attest a canary `user_data` and check the document
+/// declares the same one. Returns 0 if they agree, `DigestMismatch` if not,
+/// otherwise the [`Error`] code of the step that failed, with
+/// [`last_error_message`] saying why. The canary counts towards [`stats`].
+#[deno_bindgen]
+fn verify_digest_consistency() -> i32 {
+    let code = init_driver();
+    if code != 0 {
+        return code;
+    }
+    match r_verify_digest_consistency() {
+        Ok(()) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            log::error!("digest consistency check failed: {err}");
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+fn r_verify_digest_consistency() -> Result<(), Error> {
+    let driver = NITRO.as_ref().map_err(|_| Error::DriverUnavailable)?;
+    let described = match driver.process_request(Request::DescribeNSM) {
+        Response::DescribeNSM { digest, .. } => digest,
+        Response::Error(code) => {
+            log::warn!("the driver failed to describe the NSM: {code:?}");
+            return Err(Error::AttestationFailed);
+        }
+        response => {
+            log::error!(
+                "the driver answered a DescribeNSM request with {}",
+                response_kind(&response)
+            );
+            return Err(Error::UnexpectedResponse);
+        }
+    };
+    let document = r_attest(ByteBuf::from(DIGEST_CHECK_USER_DATA))?;
+    let (_, doc) = decode_document(&document)?;
+    if doc.digest != described {
+        log::error!(
+            "the NSM reports {described:?} but its documents declare {:?}",
+            doc.digest
+        );
+        return Err(Error::DigestMismatch);
+    }
+    Ok(())
+}
+
+/// CBOR array of the development driver's CA certificates (DER), root first and
+/// ending with the end certificate's issuer, so tests and local verifiers can
+/// trust the driver's documents without reading its PKI files. That is the
+/// configured intermediates, preceded by the default development root unless
+/// the topmost of them is itself a root. Returns an empty buffer with
+/// [`last_error`] set if the driver could not be set up. Only built with the
+/// development driver.
+#[cfg(not(feature = "nitro"))]
+#[deno_bindgen]
+fn dev_ca_chain() -> Vec<u8> {
+    if init_driver() != 0 {
+        return vec![];
+    }
+    serialize::to_vec(&DEV_CA_CHAIN.get().map_or(&[][..], Vec::as_slice))
+}
+
+/// When [`emit_test_vector`]'s document says it was created: 2030-01-01, within
+/// the validity of the default development PKI
+#[cfg(not(feature = "nitro"))]
+const TEST_VECTOR_TIMESTAMP_MS: u64 = 1_893_456_000_000;
+
+/// Hashed into [`emit_test_vector`]'s nonce, and attested as its `user_data`
+#[cfg(not(feature = "nitro"))]
+const TEST_VECTOR_SEED: &[u8] = b"deno-nitro test vector";
+
+/// A fixed attestation document, byte for byte the same on every call, build
+/// and platform, for golden test vectors shared between implementations. It is
+/// signed with the default development PKI (always the copy baked in from
+/// `test_data/`, whatever the driver was given) at [`TEST_VECTOR_TIMESTAMP_MS`],
+/// attests [`TEST_VECTOR_SEED`] with its SHA-256 as the nonce, and has the dev
+/// driver's zero PCRs. It never comes from the driver, so is not counted in
+/// [`stats`]. For testing only: the key it is signed with is public. Only built
+/// with the development driver.
+#[cfg(not(feature = "nitro"))]
+#[deno_bindgen]
+fn emit_test_vector() -> Vec<u8> {
+    let doc = AttestationDoc {
+        module_id: "deno-nitro-test-vector".to_string(),
+        digest: nsm_nitro_enclave_utils::api::nsm::Digest::SHA384,
+        timestamp: TEST_VECTOR_TIMESTAMP_MS,
+        pcrs: Pcrs::zeros().into(),
+        certificate: ByteBuf::from(include_bytes!("../test_data/end-certificate.der").to_vec()),
+        cabundle: vec![ByteBuf::from(
+            include_bytes!("../test_data/int-certificate.der").to_vec(),
+        )],
+        user_data: Some(ByteBuf::from(TEST_VECTOR_SEED)),
+        nonce: Some(ByteBuf::from(Sha256::digest(TEST_VECTOR_SEED).to_vec())),
+        public_key: None,
+    };
+    parse_signing_key(&mut include_bytes!("../test_data/end-signing-key.der").to_vec())
+        .ok()
+        .and_then(|key| sign_dev_document(&SigningKey::from(&key), doc.to_binary()))
+        .unwrap_or_default()
+}
+
+/// Root of the default development PKI, which the dev driver's chain leads to
+/// unless given other certificates
+#[cfg(not(feature = "nitro"))]
+fn dev_root() -> Result<Vec<u8>, Error> {
+    #[cfg(feature = "dev-embedded")]
+    return Ok(include_bytes!("../test_data/root-certificate.der").to_vec());
+    #[cfg(not(feature = "dev-embedded"))]
+    std::fs::read("./test_data/root-certificate.der").map_err(|err| {
+        log::error!("failed to read the development root certificate: {err}");
+        Error::InvalidRoot
+    })
+}
+
+/// Status code of the last call on this thread to a binding that records its
+/// outcome (those reporting failure with an empty buffer, [`configure`],
+/// [`nonzero_pcrs`], [`document_timestamp`], [`document_chain_len`] and the
+/// verification bindings), or
+/// 0 if it succeeded.
+#[deno_bindgen]
+fn last_error() -> i32 {
+    LAST_ERROR.get()
+}
+
+/// Description of the failure behind [`last_error`], or empty if it succeeded.
+/// Always valid UTF-8: messages are built from fixed descriptions and never
+/// quote document contents, which are rejected if their text is not UTF-8.
+#[deno_bindgen]
+fn last_error_message() -> String {
+    LAST_ERROR_MESSAGE.with_borrow(Clone::clone)
+}
+
+fn set_last_error(code: i32, message: String) {
+    LAST_ERROR.set(code);
+    LAST_ERROR_MESSAGE.set(message);
+}
+
+fn r_attest_batch(input: &[u8]) -> Result<Vec<u8>, Error> {
+    // Check the ceilings against the headers alone before attesting anything
+    let items = batch_items(input)?;
+
+    // Stream each document into the output as it is produced,
+    // so at most one document is held outside of it at a time
+    let mut out = Vec::new();
+    let mut encoder = Encoder::from(&mut out);
+    let write_error = |_| Error::InvalidArgument("failed to encode batch");
+    encoder
+        .push(Header::Array(Some(items.len())))
+        .map_err(write_error)?;
+    for item in items {
+        let document = r_attest(ByteBuf::from(item)).unwrap_or_default();
+        encoder.bytes(&document, None).map_err(write_error)?;
+    }
+
+    Ok(out)
+}
+
+/// Split a CBOR array of byte strings into borrowed items, enforcing the batch ceilings
+fn batch_items(mut input: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let max_items = BATCH_MAX_ITEMS.load(Ordering::Relaxed);
+    let max_bytes = BATCH_MAX_BYTES.load(Ordering::Relaxed);
+
+    let len = match pull_header(&mut input)? {
+        Header::Array(Some(len)) => len,
+        _ => return Err(Error::InvalidArgument("batch must be a CBOR array")),
+    };
+    if len > max_items {
+        return Err(Error::BatchTooLarge);
+    }
+
+    let mut items = Vec::with_capacity(len);
+    let mut total = 0usize;
+    for _ in 0..len {
+        let mut header = pull_header(&mut input)?;
+        if header == Header::Tag(TAG_UINT8_ARRAY) {
+            header = pull_header(&mut input)?;
+        }
+        let Header::Bytes(Some(len)) = header else {
+            return Err(Error::InvalidArgument("batch items must be byte strings"));
+        };
+        total = total.saturating_add(len);
+        if total > max_bytes {
+            return Err(Error::BatchTooLarge);
+        }
+        if len > input.len() {
+            return Err(Error::InvalidArgument("truncated batch"));
+        }
+        let (item, rest) = input.split_at(len);
+        items.push(item);
+        input = rest;
+    }
+    if !input.is_empty() {
+        return Err(Error::InvalidArgument("trailing bytes after batch"));
+    }
+
+    Ok(items)
+}
+
+/// Read one CBOR header from the front of `input`, advancing past it
+fn pull_header(input: &mut &[u8]) -> Result<Header, Error> {
+    Decoder::from(input)
+        .pull()
+        .map_err(|_| Error::InvalidArgument("invalid CBOR"))
+}
+
+/// COSE algorithm (e.g. -35 for ES384) of the driver's signatures, or 0 if it
+/// cannot attest. Read from the protected header of a probe attestation, so it
+/// reflects the NSM in production and the configured signing key in dev. The
+/// probe is asked of the driver directly, so it uses up no `attestQuotaBytes`
+/// and is not counted in [`stats`], and is only repeated until one succeeds.
+#[deno_bindgen]
+fn signing_algorithm() -> i32 {
+    if let Some(alg) = SIGNING_ALGORITHM.get() {
+        return *alg;
+    }
+
+    let document = match NITRO.as_ref().map(|driver| {
+        driver.process_request(Request::Attestation {
+            user_data: None,
+            public_key: None,
+            nonce: None,
+        })
+    }) {
+        Ok(Response::Attestation { document }) => document,
+        _ => vec![],
+    };
+    let Ok(cose) = CoseSign1::from_slice(&document) else {
+        log::warn!("unable to determine the signing algorithm, attestation failed");
+        return 0;
+    };
+    let alg = match cose.protected.header.alg {
+        Some(coset::Algorithm::Assigned(alg)) => alg as i32,
+        Some(coset::Algorithm::PrivateUse(alg)) => alg as i32,
+        _ => 0,
+    };
+    if alg != 0 {
+        log::info!("driver signs with COSE algorithm {alg}");
+        SIGNING_ALGORITHM.get_or_init(|| alg);
+    }
+    alg
+}
+
+/// Whether this process is running in a real Nitro Enclave, returning 1 if the
+/// NSM device is present and answers a `DescribeNSM` request, and 0 otherwise
+/// (always with the development driver), so callers can fail closed outside an
+/// enclave. The probe runs on the first call only, and cannot panic.
+#[deno_bindgen]
+fn in_enclave() -> u8 {
+    static IN_ENCLAVE: OnceLock<bool> = OnceLock::new();
+    let in_enclave = IN_ENCLAVE.get_or_init(|| {
+        #[cfg(feature = "nitro")]
+        {
+            std::panic::catch_unwind(|| {
+                NITRO.as_ref().is_ok_and(|driver| {
+                    matches!(
+                        driver.process_request(Request::DescribeNSM),
+                        Response::DescribeNSM { .. }
+                    )
+                })
+            })
+            .unwrap_or(false)
+        }
+        #[cfg(not(feature = "nitro"))]
+        false
+    });
+    u8::from(*in_enclave)
+}
+
+/// The NSM driver this build links against and the protocol it speaks, e.g.
+/// `nsm-nitro-enclave-utils 0.1.3; NSM 1.0.0`, for diagnostics when the two
+/// ends of an attestation disagree. In an enclave the protocol version is the
+/// one the device reports to `DescribeNSM` (asked once, on the first call);
+/// outside one it names the development driver instead.
+#[deno_bindgen]
+fn nsm_driver_version() -> String {
+    static PROTOCOL: OnceLock<String> = OnceLock::new();
+    let protocol = PROTOCOL.get_or_init(|| {
+        #[cfg(feature = "nitro")]
+        {
+            std::panic::catch_unwind(|| match NITRO.as_ref() {
+                Ok(driver) => match driver.process_request(Request::DescribeNSM) {
+                    Response::DescribeNSM {
+                        version_major,
+                        version_minor,
+                        version_patch,
+                        ..
+                    } => format!("NSM {version_major}.{version_minor}.{version_patch}"),
+                    _ => "NSM version unavailable".to_string(),
+                },
+                Err(_) => "NSM driver unavailable".to_string(),
+            })
+            .unwrap_or_else(|_| "NSM version unavailable".to_string())
+        }
+        #[cfg(not(feature = "nitro"))]
+        "development driver".to_string()
+    });
+    format!(
+        "nsm-nitro-enclave-utils {}; {protocol}",
+        env!("NSM_UTILS_VERSION")
+    )
+}
+
+/// NSM requests the bindings issue to the driver, named as in the NSM API and
+/// sorted; kept in step with the code by `supported_requests_are_issued`
+const SUPPORTED_REQUESTS: &[&str] = &["Attestation", "DescribeNSM", "GetRandom"];
+
+/// CBOR array naming the NSM requests (`Attestation`, `GetRandom`, `DescribePCR`,
+/// ...) this build wraps, so callers can detect features instead of calling a
+/// binding that may not exist.
+#[deno_bindgen]
+fn supported_requests() -> Vec<u8> {
+    serialize::to_vec(&SUPPORTED_REQUESTS)
+}
+
+/// Whether `document` was created at most `max_age_ms` milliseconds ago, returning
+/// 1 if so and 0 otherwise (including when it cannot be decoded). Timestamps more
+/// than `certClockSkewMs` (see [`InitOptions`]) ahead of the local clock are not
+/// fresh, so a forged or skewed timestamp cannot stay fresh forever.
+///
+/// Only the embedded timestamp is checked; there is no signature or chain
+/// verification, so this is only meaningful for a document that has already
+/// passed [`verify_attestation`], e.g. when deciding whether to evict it from a cache.
+#[deno_bindgen]
+fn document_is_fresh(document: &[u8], max_age_ms: u64) -> u8 {
+    match decode_document(document) {
+        Ok((_, doc)) => u8::from(check_age(doc.timestamp, max_age_ms).is_ok()),
+        Err(_) => 0,
+    }
+}
+
+/// When `document` says it was created, in milliseconds since the Unix epoch, or
+/// -1 if it cannot be decoded, with [`last_error`] saying why.
+///
+/// Like [`document_is_fresh`] this does not verify the document.
+#[deno_bindgen]
+fn document_timestamp(document: &[u8]) -> i64 {
+    match decode_document(document) {
+        Ok((_, doc)) => {
+            set_last_error(0, String::new());
+            i64::try_from(doc.timestamp).unwrap_or(i64::MAX)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            -1
+        }
+    }
+}
+
+/// How many certificates `document` carries, its leaf plus its cabundle, as a
+/// cheap pre-filter for verifiers expecting a particular chain depth (an NSM's
+/// is 5, the development driver's 2 by default), or -1 if it cannot be decoded,
+/// with [`last_error`] saying why.
+///
+/// Like [`document_is_fresh`] this does not verify the document.
+#[deno_bindgen]
+fn document_chain_len(document: &[u8]) -> i32 {
+    match decode_document(document) {
+        Ok((_, doc)) => {
+            set_last_error(0, String::new());
+            i32::try_from(doc.cabundle.len() + 1).unwrap_or(i32::MAX)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            -1
+        }
+    }
+}
+
+/// Bitmask of the PCR indices (0-31) whose value in `document` is not all zeros,
+/// whatever the bank's digest size. A debug enclave reports every PCR as zeros, so
+/// 0 means a debug document, or a failure to decode it, told apart by [`last_error`].
+///
+/// Like [`document_is_fresh`] this does not verify the document.
+#[deno_bindgen]
+fn nonzero_pcrs(document: &[u8]) -> u32 {
+    match decode_document(document) {
+        Ok((_, doc)) => {
+            set_last_error(0, String::new());
+            doc.pcrs
+                .iter()
+                .filter(|(index, value)| **index < 32 && value.iter().any(|byte| *byte != 0))
+                .fold(0, |mask, (index, _)| mask | 1 << index)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            0
+        }
+    }
+}
+
+/// Check the PCR banks of `document` in one pass, as a cheap structural check
+/// before deeper verification: that its PCR indices are distinct integers below
+/// 32 and every PCR is the length of a hash of the declared digest. Returns 0 if
+/// they are, otherwise the [`Error::PcrAnomaly`] code for the first anomaly, or
+/// the code of whatever else stopped the document decoding, with
+/// [`last_error_message`] naming it. Applies the AWS conventions even with
+/// `relaxPcrChecks` set.
+///
+/// Like [`nonzero_pcrs`] this does not verify the document.
+#[deno_bindgen]
+fn validate_pcr_banks(document: &[u8]) -> i32 {
+    let checked = check_input_size(document)
+        .and_then(|()| parsed::get_or_decode(document, || verify::decode_document(document)))
+        .and_then(|(_, doc)| verify::check_pcr_conventions(&doc));
+    match checked {
+        Ok(()) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+/// The value of PCR `index` (0-31) in `document`, or an empty buffer if the
+/// document has no such PCR, for policies that only look at one (e.g. PCR0)
+/// without taking the whole map. An index out of range, or a document that
+/// cannot be decoded, also gives an empty buffer but sets [`last_error`].
+///
+/// Like [`nonzero_pcrs`] this does not verify the document.
+#[deno_bindgen]
+fn document_pcr(document: &[u8], index: u16) -> Vec<u8> {
+    let pcr = if usize::from(index) >= verify::MAX_PCRS {
+        Err(Error::InvalidArgument("PCR index must be below 32"))
+    } else {
+        decode_document(document).map(|(_, doc)| {
+            doc.pcrs
+                .get(&usize::from(index))
+                .map_or_else(Vec::new, |value| value.to_vec())
+        })
+    };
+    match pcr {
+        Ok(value) => {
+            set_last_error(0, String::new());
+            value
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+/// Whether `document` declares its PCRs were computed with the hash algorithm
+/// `expected_alg` (a COSE algorithm id: -16 for SHA-256, -43 for SHA-384 or -44
+/// for SHA-512), returning 1 if so and 0 otherwise, so a policy requiring SHA-384
+/// rejects documents using anything else. Only supported algorithms are
+/// accepted as `expected_alg`; another value, or a document that cannot be
+/// decoded, also gives 0 but sets [`last_error`].
+///
+/// Like [`nonzero_pcrs`] this does not verify the document.
+#[deno_bindgen]
+fn require_digest(document: &[u8], expected_alg: i32) -> u8 {
+    match r_require_digest(document, expected_alg) {
+        Ok(matches) => {
+            set_last_error(0, String::new());
+            u8::from(matches)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            0
+        }
+    }
+}
+
+/// Digests an NSM may compute PCRs with
+const SUPPORTED_DIGESTS: [iana::Algorithm; 3] = [
+    iana::Algorithm::SHA_256,
+    iana::Algorithm::SHA_384,
+    iana::Algorithm::SHA_512,
+];
+
+fn r_require_digest(document: &[u8], expected_alg: i32) -> Result<bool, Error> {
+    if !SUPPORTED_DIGESTS
+        .iter()
+        .any(|alg| *alg as i32 == expected_alg)
+    {
+        return Err(Error::InvalidArgument(
+            "expected digest must be SHA-256, SHA-384 or SHA-512",
+        ));
+    }
+    let (_, doc) = decode_document(document)?;
+    Ok(verify::digest_algorithm(doc.digest) as i32 == expected_alg)
+}
+
+/// Whether the `public_key` embedded in `document` is exactly `expected_spki_der`,
+/// a DER SubjectPublicKeyInfo (e.g. taken from a CSR or certificate), returning 1
+/// if so and 0 otherwise. The comparison runs in constant time.
+///
+/// The document is not verified, so first check it with [`verify_attestation`].
+/// A document that cannot be decoded, or an `expected_spki_der` that is not an
+/// SPKI, also gives 0 but sets [`last_error`].
+#[deno_bindgen]
+fn verify_bound_public_key(document: &[u8], expected_spki_der: &[u8]) -> u8 {
+    match r_bound_public_key_matches(document, expected_spki_der) {
+        Ok(matches) => {
+            set_last_error(0, String::new());
+            u8::from(matches)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            0
+        }
+    }
+}
+
+fn r_bound_public_key_matches(document: &[u8], expected_spki_der: &[u8]) -> Result<bool, Error> {
+    SubjectPublicKeyInfoRef::from_der(expected_spki_der)
+        .map_err(|_| Error::InvalidArgument("expected key is not a DER SubjectPublicKeyInfo"))?;
+    let (_, doc) = decode_document(document)?;
+    Ok(doc
+        .public_key
+        .is_some_and(|key| bool::from(key.as_slice().ct_eq(expected_spki_der))))
+}
+
+/// Whether the `user_data` of `document` is the SHA-256 of `original_payload`,
+/// as [`attest_hashed`] attests it, returning 1 if so and 0 otherwise. A
+/// [`BootSessionHeader`] around the `user_data` is looked through. The
+/// comparison runs in constant time.
+///
+/// Like [`verify_bound_public_key`] the document is not verified, so first check
+/// it with [`verify_attestation`]. A document that cannot be decoded also gives 0
+/// but sets [`last_error`].
+#[deno_bindgen]
+fn verify_user_data_hash(document: &[u8], original_payload: &[u8]) -> u8 {
+    report_user_data_match(document, &Sha256::digest(original_payload))
+}
+
+/// Whether `document` commits to `cert_der` as [`attest_tls_cert`] binds it,
+/// i.e. its `user_data` is the SHA-384 of the certificate, returning 1 if so and
+/// 0 otherwise. A [`BootSessionHeader`] around the `user_data` is looked through.
+/// The comparison runs in constant time.
+///
+/// Like [`verify_bound_public_key`] the document is not verified, so first check
+/// it with [`verify_attestation`]. A document that cannot be decoded also gives 0
+/// but sets [`last_error`].
+#[deno_bindgen]
+fn verify_tls_cert_binding(document: &[u8], cert_der: &[u8]) -> u8 {
+    report_user_data_match(document, &Sha384::digest(cert_der))
+}
+
+/// Compare the `user_data` of `document`, looking through a
+/// [`BootSessionHeader`], with `expected` in constant time, giving 1 if they
+/// match and 0 otherwise, or 0 with [`last_error`] set if it cannot be decoded
+fn report_user_data_match(document: &[u8], expected: &[u8]) -> u8 {
+    match decode_document(document) {
+        Ok((_, doc)) => {
+            set_last_error(0, String::new());
+            let user_data = doc.user_data.map(|data| {
+                serialize::from_slice::<BootSessionHeader>(&data)
+                    .map_or(data, |header| header.user_data)
+            });
+            u8::from(user_data.is_some_and(|data| bool::from(data.as_slice().ct_eq(expected))))
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            0
+        }
+    }
+}
+
+/// Verify `signature` over `signed_data` was made with the key a trusted
+/// attestation binds, tying application signatures back to the enclave.
+/// `document` is first verified against `root` like [`verify_attestation`] (an
+/// empty `root` selects the default root), then its `public_key`, which must be
+/// an ECDSA P-384 SubjectPublicKeyInfo, checks `signature`: the 96 byte `r || s`
+/// of an ECDSA P-384 / SHA-384 signature, as WebCrypto produces. Signatures from
+/// [`attest_and_sign`] cover [`bound_key::CHALLENGE_CONTEXT`] followed by the
+/// challenge. Returns 0 on success, otherwise the [`Error`] code.
+#[deno_bindgen]
+fn verify_signature(signed_data: &[u8], signature: &[u8], document: &[u8], root: &[u8]) -> i32 {
+    match r_verify_signature(signed_data, signature, document, root) {
+        Ok(()) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            log::debug!("signature rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+fn r_verify_signature(
+    signed_data: &[u8],
+    signature: &[u8],
+    document: &[u8],
+    root: &[u8],
+) -> Result<(), Error> {
+    let doc = resolve_root(root).and_then(|root| r_verify(document, root))?;
+    let public_key = doc.public_key.ok_or(Error::PolicyViolation(
+        "document does not bind a public key",
+    ))?;
+    let key = VerifyingKey::from_public_key_der(&public_key)
+        .map_err(|_| Error::PolicyViolation("bound public key is not ECDSA P-384"))?;
+    let signature = Signature::from_slice(signature).map_err(|_| Error::SignatureInvalid)?;
+    key.verify(signed_data, &signature)
+        .map_err(|_| Error::SignatureInvalid)
+}
+
+/// SHA-256 of `document` exactly as given (the COSE_Sign1 bytes the NSM produced,
+/// signature included), as a compact id for logs and cache keys. Nothing is
+/// re-encoded, so the fingerprint of a given document never changes between
+/// versions. Returns an empty buffer with [`last_error`] set if `document` is not
+/// an attestation document.
+#[deno_bindgen]
+fn document_fingerprint(document: &[u8]) -> Vec<u8> {
+    match decode_document(document) {
+        Ok(_) => {
+            set_last_error(0, String::new());
+            Sha256::digest(document).to_vec()
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+/// [`document_key_commitment`] of a document binding no public key
+const NO_KEY_COMMITMENT: [u8; 32] = [0; 32];
+
+/// Commitment to the key a document binds, for protocol transcripts: the SHA-256
+/// of its `public_key` field's bytes exactly as attested (for keys bound with
+/// [`attest_key`], the DER SubjectPublicKeyInfo), or [`NO_KEY_COMMITMENT`] if
+/// the field is absent or empty. Documents binding the same key commit to the
+/// same value whatever else they attest. Like [`document_fingerprint`] this does
+/// not verify the document; an empty buffer with [`last_error`] set means it
+/// could not be decoded.
+#[deno_bindgen]
+fn document_key_commitment(document: &[u8]) -> Vec<u8> {
+    match decode_document(document) {
+        Ok((_, doc)) => {
+            set_last_error(0, String::new());
+            match doc.public_key {
+                Some(public_key) if !public_key.is_empty() => Sha256::digest(public_key).to_vec(),
+                _ => NO_KEY_COMMITMENT.to_vec(),
+            }
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+/// The time [`set_test_clock`] froze the clock at, in milliseconds since the
+/// Unix epoch, or 0 while it tracks the system clock
+#[cfg(not(feature = "nitro"))]
+static TEST_CLOCK_MS: AtomicU64 = AtomicU64::new(0);
+
+/// Milliseconds since the Unix epoch, the unit of attestation timestamps. Every
+/// freshness, age and certificate validity check reads the time from here.
+fn now_ms() -> u64 {
+    #[cfg(not(feature = "nitro"))]
+    match TEST_CLOCK_MS.load(Ordering::Relaxed) {
+        0 => {}
+        frozen => return frozen,
+    }
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Freeze the clock timestamps are checked against at `unix_ms` milliseconds
+/// since the Unix epoch, so tests of freshness, maximum ages and certificate
+/// validity are deterministic; 0 goes back to the system clock. Documents the
+/// driver makes still carry the real time. Returns 0. Only built with the
+/// development driver.
+#[cfg(not(feature = "nitro"))]
+#[deno_bindgen]
+fn set_test_clock(unix_ms: u64) -> i32 {
+    TEST_CLOCK_MS.store(unix_ms, Ordering::Relaxed);
+    0
+}
+
+/// Verify `document` chains to `root` (DER) and is signed by its leaf certificate.
+/// An empty `root` selects the default root (see [`load_default_root`]).
+/// Returns 0 on success, otherwise the [`Error`] code.
+#[deno_bindgen]
+fn verify_attestation(document: &[u8], root: &[u8]) -> i32 {
+    match resolve_root(root).and_then(|root| r_verify(document, root)) {
+        Ok(_) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            log::debug!("attestation rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+/// Verify a chain of attestations where each attests to having verified the one
+/// before: `documents` is a CBOR array of documents, innermost first, and each
+/// one after the first must carry the [`document_fingerprint`] of the previous
+/// document somewhere in its `user_data` (e.g. as the whole input to `attest`).
+/// Every document must also verify against `root` like [`verify_attestation`].
+///
+/// Returns -1 if the whole chain verifies, otherwise the index of the first
+/// document that failed, with [`last_error`] set to why (a `PolicyViolation` if
+/// it verified but does not link to the previous one, `InputTooLarge` if it is
+/// larger than `maxInputBytes`). Returns -2, with [`last_error`] set, without
+/// checking any document, if `documents` is not a non-empty array, exceeds the
+/// batch ceilings (see [`set_batch_limits`]), or `root` is invalid.
+#[deno_bindgen]
+fn verify_attestation_chain(documents: &[u8], root: &[u8]) -> i32 {
+    match r_verify_attestation_chain(documents, root) {
+        Ok(()) => {
+            set_last_error(0, String::new());
+            -1
+        }
+        Err((Some(index), err)) => {
+            log::debug!("attestation chain rejected at document {index}: {err}");
+            set_last_error(err.code(), err.to_string());
+            index as i32
+        }
+        Err((None, err)) => {
+            log::debug!("attestation chain rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            -2
+        }
+    }
+}
+
+/// Fails with the index of the document that failed, or `None` for a failure of
+/// the input as a whole
+fn r_verify_attestation_chain(documents: &[u8], root: &[u8]) -> Result<(), (Option<usize>, Error)> {
+    let documents = batch_items(documents).map_err(|err| (None, err))?;
+    if documents.is_empty() {
+        return Err((None, Error::InvalidArgument("documents must not be empty")));
+    }
+    let roots = resolve_root(root)
+        .and_then(parse_trusted_roots)
+        .map_err(|err| (None, err))?;
+
+    let mut previous: Option<[u8; 32]> = None;
+    for (index, document) in documents.iter().enumerate() {
+        let doc = check_input_size(document)
+            .and_then(|()| r_verify_against(document, &roots))
+            .map_err(|err| (Some(index), err))?;
+        if let Some(fingerprint) = previous {
+            let user_data = doc
+                .user_data
+                .as_ref()
+                .map_or(&[][..], |data| data.as_slice());
+            if !user_data
+                .windows(fingerprint.len())
+                .any(|window| window == fingerprint)
+            {
+                return Err((
+                    Some(index),
+                    Error::PolicyViolation("user_data does not commit to the previous document"),
+                ));
+            }
+        }
+        previous = Some(Sha256::digest(document).into());
+    }
+    Ok(())
+}
+
+/// The caller's `root`, or the default root (see [`load_default_root`]) if empty
+fn resolve_root(root: &[u8]) -> Result<&[u8], Error> {
+    if root.is_empty() {
+        DEFAULT_ROOT.as_deref().map_err(|err| *err)
+    } else {
+        Ok(root)
+    }
+}
+
+/// Version written by [`build_snapshot`] and required by [`verify_against_snapshot`]
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// A self-contained verification policy for offline verifiers, as CBOR
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct Snapshot {
+    /// Omitted by callers of [`build_snapshot`], which sets it
+    #[serde(default)]
+    version: u32,
+    /// DER root certificate or PEM bundle of roots the document must chain to
+    root: ByteBuf,
+    /// Accepted values by PCR index; PCRs not listed may hold anything
+    #[serde(default)]
+    pcrs: BTreeMap<usize, Vec<ByteBuf>>,
+    /// Accepted module IDs, or empty to accept any
+    #[serde(default)]
+    module_ids: Vec<String>,
+    /// Greatest accepted document age in milliseconds, or 0 for no limit
+    #[serde(default)]
+    max_age_ms: u64,
+}
+
+impl Snapshot {
+    fn check(&self) -> Result<(), Error> {
+        verify::parse_roots(&self.root)?;
+        check_pcr_constraints(&self.pcrs)
+    }
+
+    /// Check a verified document meets every constraint of the snapshot
+    fn admits(&self, doc: &AttestationDoc) -> Result<(), Error> {
+        check_constraints(doc, &self.pcrs, &self.module_ids, self.max_age_ms)
+    }
+}
+
+/// Check every PCR constraint names an index below 32 and accepts at least one
+/// value, each of a valid PCR length
+fn check_pcr_constraints(pcrs: &BTreeMap<usize, Vec<ByteBuf>>) -> Result<(), Error> {
+    for (index, allowed) in pcrs {
+        if *index >= 32 {
+            return Err(Error::InvalidArgument("PCR constraint index out of range"));
+        }
+        if allowed.is_empty() {
+            return Err(Error::InvalidArgument(
+                "PCR constraint has no accepted values",
+            ));
+        }
+        if allowed
+            .iter()
+            .any(|value| ![32, 48, 64].contains(&value.len()))
+        {
+            return Err(Error::InvalidArgument(
+                "PCR constraint value has the wrong length",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Why a document timestamp failed [`check_age`]
+enum Stale {
+    /// Older than accepted, being this many milliseconds old
+    TooOld(u64),
+    /// Ahead of the local clock by more than `certClockSkewMs`, by this many
+    /// milliseconds
+    InFuture(u64),
+}
+
+impl Stale {
+    fn reason(&self) -> &'static str {
+        match self {
+            Stale::TooOld(_) => "document too old",
+            Stale::InFuture(_) => "document timestamp is in the future",
+        }
+    }
+}
+
+/// Check a document `timestamp` is at most `max_age_ms` old, and at most
+/// `certClockSkewMs` ahead of the local clock: no NSM timestamps documents
+/// later than that, so a forged or skewed timestamp passes no age limit
+fn check_age(timestamp: u64, max_age_ms: u64) -> Result<(), Stale> {
+    let now = now_ms();
+    let skew = u64::from(CERT_CLOCK_SKEW_MS.load(Ordering::Relaxed));
+    if timestamp > now.saturating_add(skew) {
+        return Err(Stale::InFuture(timestamp - now));
+    }
+    let age = now.saturating_sub(timestamp);
+    if age > max_age_ms {
+        return Err(Stale::TooOld(age));
+    }
+    Ok(())
+}
+
+/// Check a verified document's module ID, age and PCRs, the constraints shared
+/// by [`Snapshot`] and [`Policy`]; empty or zero constraints accept anything
+fn check_constraints(
+    doc: &AttestationDoc,
+    pcrs: &BTreeMap<usize, Vec<ByteBuf>>,
+    module_ids: &[String],
+    max_age_ms: u64,
+) -> Result<(), Error> {
+    if !module_ids.is_empty() && !module_ids.contains(&doc.module_id) {
+        return Err(Error::PolicyViolation("module_id not accepted"));
+    }
+    if max_age_ms != 0 {
+        check_age(doc.timestamp, max_age_ms)
+            .map_err(|stale| Error::PolicyViolation(stale.reason()))?;
+    }
+    for (index, allowed) in pcrs {
+        match doc.pcrs.get(index) {
+            Some(value) if allowed.contains(value) => {}
+            _ => return Err(Error::PolicyViolation("PCR value not accepted")),
+        }
+    }
+    Ok(())
+}
+
+/// Build a snapshot from `spec`, a CBOR map with the fields of [`Snapshot`]
+/// (`root`, and optionally `pcrs`, `moduleIds` and `maxAgeMs`). The constraints
+/// are checked and written back deterministically, so equal policies always give
+/// byte-identical snapshots. Returns an empty buffer with [`last_error`] set if
+/// `spec` is invalid.
+#[deno_bindgen]
+fn build_snapshot(spec: &[u8]) -> Vec<u8> {
+    match r_build_snapshot(spec) {
+        Ok(snapshot) => {
+            set_last_error(0, String::new());
+            snapshot
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+fn r_build_snapshot(spec: &[u8]) -> Result<Vec<u8>, Error> {
+    check_input_size(spec)?;
+    let mut snapshot: Snapshot = serialize::from_slice(spec)
+        .map_err(|_| Error::InvalidArgument("snapshot spec is not a valid CBOR map"))?;
+    snapshot.version = SNAPSHOT_VERSION;
+    snapshot.check()?;
+
+    Ok(serialize::to_vec(&snapshot))
+}
+
+/// Verify `document` against its chain and signature like [`verify_attestation`],
+/// using the root in `snapshot` (from [`build_snapshot`]), then check it meets the
+/// snapshot's PCR, module ID and age constraints. Returns 0 on success, otherwise
+/// the [`Error`] code.
+#[deno_bindgen]
+fn verify_against_snapshot(document: &[u8], snapshot: &[u8]) -> i32 {
+    match r_verify_against_snapshot(document, snapshot) {
+        Ok(()) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            log::debug!("attestation rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+fn r_verify_against_snapshot(document: &[u8], snapshot: &[u8]) -> Result<(), Error> {
+    check_input_size(snapshot)?;
+    let snapshot: Snapshot =
+        serialize::from_slice(snapshot).map_err(|_| Error::InvalidArgument("not a snapshot"))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(Error::InvalidArgument("unsupported snapshot version"));
+    }
+    snapshot.check()?;
+
+    let doc = r_verify(document, &snapshot.root)?;
+    snapshot.admits(&doc)
+}
+
+/// Every constraint [`verify_with_policy`] can check, as CBOR. Omitted fields
+/// accept anything.
+#[derive(serde::Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct Policy {
+    /// DER root certificate or PEM bundle of roots the document must chain to, or
+    /// the default root (see [`load_default_root`]) if omitted or empty
+    root: Option<ByteBuf>,
+    /// Accepted values by PCR index, as in [`Snapshot`]
+    pcrs: BTreeMap<usize, Vec<ByteBuf>>,
+    /// Accepted module IDs, or empty to accept any
+    module_ids: Vec<String>,
+    /// Nonce the document must carry, e.g. a relying party's challenge
+    nonce: Option<ByteBuf>,
+    /// Greatest accepted document age in milliseconds, or 0 for no limit
+    max_age_ms: u64,
+    /// Reject documents from debug enclaves, whose PCRs are all zeros
+    reject_debug: bool,
+    /// COSE algorithm the PCRs must have been computed with, as in [`require_digest`]
+    required_digest: Option<i32>,
+    /// DER SubjectPublicKeyInfos the leaf certificate's key must be one of, or
+    /// empty to accept any key the root vouches for
+    leaf_keys: Vec<ByteBuf>,
+}
+
+impl Policy {
+    fn check(&self) -> Result<(), Error> {
+        if let Some(alg) = self.required_digest
+            && !SUPPORTED_DIGESTS
+                .iter()
+                .any(|supported| *supported as i32 == alg)
+        {
+            return Err(Error::InvalidArgument(
+                "requiredDigest must be SHA-256, SHA-384 or SHA-512",
+            ));
+        }
+        if self
+            .leaf_keys
+            .iter()
+            .any(|key| SubjectPublicKeyInfoRef::from_der(key).is_err())
+        {
+            return Err(Error::InvalidArgument(
+                "leafKeys must be DER SubjectPublicKeyInfos",
+            ));
+        }
+        check_pcr_constraints(&self.pcrs)
+    }
+
+    /// Check a verified document meets every constraint of the policy, in the
+    /// order they are documented on [`verify_with_policy`]
+    fn admits(&self, doc: &AttestationDoc) -> Result<(), Error> {
+        check_constraints(doc, &self.pcrs, &self.module_ids, self.max_age_ms)?;
+        if let Some(expected) = &self.nonce {
+            let nonce = doc.nonce.as_ref().map_or(&[][..], |nonce| nonce.as_slice());
+            if !bool::from(nonce.ct_eq(expected.as_slice())) {
+                return Err(Error::PolicyViolation("nonce does not match"));
+            }
+        }
+        if self.reject_debug
+            && doc
+                .pcrs
+                .values()
+                .all(|value| value.iter().all(|byte| *byte == 0))
+        {
+            return Err(Error::PolicyViolation("document is from a debug enclave"));
+        }
+        if let Some(alg) = self.required_digest
+            && verify::digest_algorithm(doc.digest) as i32 != alg
+        {
+            return Err(Error::PolicyViolation("PCR digest not accepted"));
+        }
+        if !self.leaf_keys.is_empty() {
+            let leaf_key = verify::leaf_public_key(doc)?;
+            if !self
+                .leaf_keys
+                .iter()
+                .any(|key| key.as_slice() == leaf_key.as_slice())
+            {
+                return Err(Error::PolicyViolation("leaf public key is not pinned"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The root documents must chain to, see [`resolve_root`]
+    fn root(&self) -> Result<&[u8], Error> {
+        resolve_root(self.root.as_ref().map_or(&[][..], |root| root.as_slice()))
+    }
+
+    /// Lines of [`explain_verification`] for each constraint, checked against
+    /// `doc` in the same order as by [`Policy::admits`], but without stopping at
+    /// the first failure
+    fn explain(&self, doc: &AttestationDoc) -> Vec<String> {
+        let mut lines = vec![explained(
+            "module ID",
+            (!self.module_ids.is_empty()).then(|| {
+                if self.module_ids.contains(&doc.module_id) {
+                    Ok(())
+                } else {
+                    Err(format!("{:?} is not accepted", doc.module_id))
+                }
+            }),
+        )];
+
+        lines.push(explained(
+            "age",
+            (self.max_age_ms != 0).then(|| match check_age(doc.timestamp, self.max_age_ms) {
+                Ok(()) => Ok(()),
+                Err(Stale::TooOld(age)) => Err(format!(
+                    "{age} ms old, at most {} ms accepted",
+                    self.max_age_ms
+                )),
+                Err(Stale::InFuture(ahead)) => Err(format!("{ahead} ms in the future")),
+            }),
+        ));
+
+        if self.pcrs.is_empty() {
+            lines.push(explained("PCRs", None));
+        }
+        for (index, allowed) in &self.pcrs {
+            let actual = doc.pcrs.get(index);
+            if actual.is_some_and(|value| allowed.contains(value)) {
+                lines.push(explained(&format!("PCR{index}"), Some(Ok(()))));
+                continue;
+            }
+            lines.push(format!("PCR{index}: failed"));
+            for value in allowed {
+                lines.push(format!("  expected: {}", hex(value)));
+            }
+            lines.push(format!(
+                "  actual:   {}",
+                actual.map_or("absent".to_string(), |value| hex(value))
+            ));
+        }
+
+        lines.push(explained(
+            "nonce",
+            self.nonce.as_ref().map(|expected| {
+                let nonce = doc.nonce.as_ref().map_or(&[][..], |nonce| nonce.as_slice());
+                if bool::from(nonce.ct_eq(expected.as_slice())) {
+                    Ok(())
+                } else {
+                    Err("does not match".to_string())
+                }
+            }),
+        ));
+
+        lines.push(explained(
+            "debug mode",
+            self.reject_debug.then(|| {
+                if doc
+                    .pcrs
+                    .values()
+                    .all(|value| value.iter().all(|byte| *byte == 0))
+                {
+                    Err("every PCR is zeros, as in a debug enclave".to_string())
+                } else {
+                    Ok(())
+                }
+            }),
+        ));
+
+        lines.push(explained(
+            "digest",
+            self.required_digest.map(|required| {
+                let alg = verify::digest_algorithm(doc.digest) as i32;
+                if alg == required {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "{} where {} is required",
+                        digest_name(alg),
+                        digest_name(required)
+                    ))
+                }
+            }),
+        ));
+
+        lines.push(explained(
+            "leaf key",
+            (!self.leaf_keys.is_empty()).then(|| {
+                let leaf_key = verify::leaf_public_key(doc).map_err(|err| err.to_string())?;
+                if self
+                    .leaf_keys
+                    .iter()
+                    .any(|key| key.as_slice() == leaf_key.as_slice())
+                {
+                    Ok(())
+                } else {
+                    Err("not one of the pinned keys".to_string())
+                }
+            }),
+        ));
+        lines
+    }
+}
+
+/// Verify `document` and check it against every constraint of `policy` in one
+/// call: a CBOR map with the fields of [`Policy`] (`root`, `pcrs`, `moduleIds`,
+/// `nonce`, `maxAgeMs`, `rejectDebug`, `requiredDigest` and `leafKeys`, all
+/// optional). The chain and signature are verified like [`verify_attestation`],
+/// then the module ID, age, PCRs, nonce, debug mode, digest and leaf key are
+/// checked in that order, and last, with `seenNonceCapacity` set (see
+/// [`InitOptions`]), that the nonce was not accepted before.
+/// Returns 0 if everything passes, otherwise the [`Error`] code of the first
+/// failure (a `PolicyViolation` for a constraint the document breaks,
+/// `NonceReplayed` for a nonce already accepted).
+#[deno_bindgen]
+fn verify_with_policy(document: &[u8], policy: &[u8]) -> i32 {
+    match r_verify_with_policy(document, policy) {
+        Ok(_) => {
+            set_last_error(0, String::new());
+            0
+        }
+        Err(err) => {
+            log::debug!("attestation rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            err.code()
+        }
+    }
+}
+
+/// Outcome of [`verify_with_policy_cbor`]
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PolicyVerification {
+    /// 0, or the [`Error`] code of the first failure
+    code: i32,
+    /// Why verification failed, or empty
+    message: String,
+    /// The accepted document's payload, or `None` if it was rejected
+    document: Option<VerifiedDocument>,
+}
+
+/// Payload fields of a document [`verify_with_policy_cbor`] accepted
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct VerifiedDocument {
+    module_id: String,
+    /// Milliseconds since the Unix epoch
+    timestamp: u64,
+    /// COSE algorithm identifier of the PCR digest, as [`require_digest`] takes
+    digest: i32,
+    pcrs: BTreeMap<usize, ByteBuf>,
+    user_data: Option<ByteBuf>,
+    nonce: Option<ByteBuf>,
+    public_key: Option<ByteBuf>,
+}
+
+/// Like [`verify_with_policy`], but returns the outcome together with the
+/// accepted document's payload, as a CBOR [`PolicyVerification`] map: `{ code,
+/// message, document }`, where `document` is `{ moduleId, timestamp, digest,
+/// pcrs, userData, nonce, publicKey }` on success and null otherwise. PCRs keep
+/// their integer indices and byte strings stay byte strings, so callers read
+/// the verified fields with a CBOR decoder instead of decoding the document
+/// again. [`last_error`] is set as by [`verify_with_policy`].
+#[deno_bindgen]
+fn verify_with_policy_cbor(document: &[u8], policy: &[u8]) -> Vec<u8> {
+    let verification = match r_verify_with_policy(document, policy) {
+        Ok(doc) => {
+            set_last_error(0, String::new());
+            PolicyVerification {
+                code: 0,
+                message: String::new(),
+                document: Some(VerifiedDocument {
+                    module_id: doc.module_id,
+                    timestamp: doc.timestamp,
+                    digest: verify::digest_algorithm(doc.digest) as i32,
+                    pcrs: doc.pcrs,
+                    user_data: doc.user_data,
+                    nonce: doc.nonce,
+                    public_key: doc.public_key,
+                }),
+            }
+        }
+        Err(err) => {
+            log::debug!("attestation rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            PolicyVerification {
+                code: err.code(),
+                message: err.to_string(),
+                document: None,
+            }
+        }
+    };
+    serialize::to_vec(&verification)
+}
+
+/// Verify `document` against `policy` like [`verify_with_policy`] and, if it
+/// passes, return the public key bound into it as a DER SubjectPublicKeyInfo, so
+/// relying parties can import it (e.g. into WebCrypto) whatever encoding the
+/// enclave bound: an SPKI comes back re-encoded as canonical DER, and a bare SEC1
+/// P-384 point wrapped in one. Returns an empty buffer if the document passed
+/// with no key bound (with [`last_error`] clear), or with [`last_error`] set if
+/// it failed verification or its key is in neither encoding.
+#[deno_bindgen]
+fn verify_and_export_key(document: &[u8], policy: &[u8]) -> Vec<u8> {
+    let exported = r_verify_with_policy(document, policy).and_then(|doc| match doc.public_key {
+        Some(key) if !key.is_empty() => verify::normalize_public_key(&key),
+        _ => Ok(vec![]),
+    });
+    match exported {
+        Ok(key) => {
+            set_last_error(0, String::new());
+            key
+        }
+        Err(err) => {
+            log::debug!("attestation rejected: {err}");
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+fn r_verify_with_policy(document: &[u8], policy: &[u8]) -> Result<AttestationDoc, Error> {
+    r_check_with_policy(document, policy, true)
+}
+
+/// Verify `document` against `policy`, then, with `seenNonceCapacity` set (see
+/// [`InitOptions`]), reject it if its nonce was accepted before. Only a `consume`
+/// check remembers the nonce as accepted, and only once everything else passed,
+/// so a rejected document never uses its nonce up.
+fn r_check_with_policy(
+    document: &[u8],
+    policy: &[u8],
+    consume: bool,
+) -> Result<AttestationDoc, Error> {
+    let policy = parse_policy(policy)?;
+    let roots = parse_trusted_roots(policy.root()?)?;
+    check_against_policy(document, &policy, &roots, consume)
+}
+
+/// [`r_check_with_policy`] with the policy and its roots already parsed
+fn check_against_policy(
+    document: &[u8],
+    policy: &Policy,
+    roots: &[verify::Root],
+    consume: bool,
+) -> Result<AttestationDoc, Error> {
+    let doc = r_verify_against(document, roots)?;
+    policy.admits(&doc)?;
+    if let Some(nonce) = &doc.nonce
+        && !replay::first_use(nonce, consume)
+    {
+        return Err(Error::NonceReplayed);
+    }
+    Ok(doc)
+}
+
+/// Verify every document of `documents`, a CBOR array of byte strings, against
+/// `policy` as [`verify_with_policy`] does, for bulk jobs such as auditing a
+/// day's attestations in one call. The policy and its roots are parsed once for
+/// the whole batch, and chains and signatures go through the same [`cache`].
+/// Nonces are used up as they are accepted, so a document whose nonce an earlier
+/// one in the batch used is `NonceReplayed` while `seenNonceCapacity` is set.
+///
+/// Returns a CBOR array with one result code per document, in order: 0 if it
+/// passed, otherwise the [`Error`] code of its first failure, `InputTooLarge`
+/// for one larger than `maxInputBytes`. Returns an empty buffer with
+/// [`last_error`] set, without checking any document, if `documents` is not such
+/// an array, exceeds the batch ceilings (see [`set_batch_limits`]), or `policy`
+/// is invalid.
+#[deno_bindgen]
+fn verify_batch(documents: &[u8], policy: &[u8]) -> Vec<u8> {
+    match r_verify_batch(documents, policy) {
+        Ok(codes) => {
+            set_last_error(0, String::new());
+            serialize::to_vec(&codes)
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            vec![]
+        }
+    }
+}
+
+fn r_verify_batch(documents: &[u8], policy: &[u8]) -> Result<Vec<i32>, Error> {
+    let documents = batch_items(documents)?;
+    let policy = parse_policy(policy)?;
+    let roots = parse_trusted_roots(policy.root()?)?;
+
+    Ok(documents
+        .iter()
+        .enumerate()
+        .map(|(index, document)| {
+            match check_input_size(document)
+                .and_then(|()| check_against_policy(document, &policy, &roots, true))
+            {
+                Ok(_) => 0,
+                Err(err) => {
+                    log::debug!("batch document {index} rejected: {err}");
+                    err.code()
+                }
+            }
+        })
+        .collect())
+}
+
+fn parse_policy(policy: &[u8]) -> Result<Policy, Error> {
+    check_input_size(policy)?;
+    let policy: Policy = serialize::from_slice(policy)
+        .map_err(|_| Error::InvalidArgument("policy is not a valid CBOR map"))?;
+    policy.check()?;
+    Ok(policy)
+}
+
+/// Explain, for people reading logs, how `document` fares against `policy` (as
+/// taken by [`verify_with_policy`]), one line per check: first the outcome, as
+/// [`verify_with_policy`] would report it, then whether the chain and signature
+/// and each constraint passed, failed or was not checked, in the order they are
+/// checked in. Failed PCRs list the accepted values and the document's, in hex.
+/// Nothing is shown that the document does not already make public, and not
+/// even that for the nonce. A policy that is invalid, or a document that cannot
+/// be decoded, is explained by the outcome alone. [`last_error`] is set as by
+/// [`verify_with_policy`], but an accepted nonce is not used up.
+#[deno_bindgen]
+fn explain_verification(document: &[u8], policy: &[u8]) -> String {
+    let outcome = r_check_with_policy(document, policy, false);
+    let mut lines = vec![match &outcome {
+        Ok(_) => {
+            set_last_error(0, String::new());
+            "attestation accepted".to_string()
+        }
+        Err(err) => {
+            set_last_error(err.code(), err.to_string());
+            format!("attestation rejected: {err}")
+        }
+    }];
+    if let Ok(policy) = parse_policy(policy)
+        && let Ok(root) = policy.root()
+        && let Ok((_, doc)) = decode_document(document)
+    {
+        lines.push(explained(
+            "chain and signature",
+            Some(
+                r_verify(document, root)
+                    .map(drop)
+                    .map_err(|err| err.to_string()),
+            ),
+        ));
+        lines.extend(policy.explain(&doc));
+    }
+    lines.join("\n")
+}
+
+/// One line of [`explain_verification`]: `None` for a check the policy does not
+/// ask for, otherwise its outcome and why it failed
+fn explained(check: &str, outcome: Option<Result<(), String>>) -> String {
+    match outcome {
+        None => format!("{check}: not checked"),
+        Some(Ok(())) => format!("{check}: passed"),
+        Some(Err(why)) => format!("{check}: failed, {why}"),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Name of a COSE hash algorithm [`require_digest`] accepts
+fn digest_name(alg: i32) -> &'static str {
+    match alg {
+        -16 => "SHA-256",
+        -43 => "SHA-384",
+        -44 => "SHA-512",
+        _ => "an unsupported digest",
+    }
+}
+
+/// Prefer the root named by `DENO_NITRO_ROOT_CERT`, falling back to the embedded AWS root.
+/// An override that cannot be read or parsed is an error rather than a silent fallback.
+fn load_default_root() -> Result<Vec<u8>, Error> {
+    init_logging();
+    let Some(path) = std::env::var_os(ROOT_CERT_ENV) else {
+        log::info!("using embedded AWS Nitro Enclaves root certificate");
+        return Ok(AWS_NITRO_ROOT.to_vec());
+    };
+
+    let der = std::fs::read(&path).map_err(|err| {
+        log::error!("failed to read {ROOT_CERT_ENV} ({}): {err}", path.display());
+        Error::InvalidRoot
+    })?;
+    verify::parse_roots(&der).inspect_err(|_| {
+        log::error!(
+            "{ROOT_CERT_ENV} ({}) is not a DER certificate or PEM bundle of them",
+            path.display()
+        );
+    })?;
+    log::info!(
+        "using root certificate from {ROOT_CERT_ENV} ({})",
+        path.display()
+    );
+
+    Ok(der)
+}
+
+/// Decode `document` like [`verify::decode_document`] if [`check_input_size`]
+/// passes it, reusing an earlier decode from the [`parsed`] cache when enabled,
+/// and check its PCRs follow the AWS conventions unless `relaxPcrChecks` is set
+fn decode_document(document: &[u8]) -> Result<(CoseSign1, AttestationDoc), Error> {
+    check_input_size(document)?;
+    let (cose, doc) = parsed::get_or_decode(document, || verify::decode_document(document))
+        .map_err(Error::generalize)?;
+    if !RELAX_PCR_CHECKS.load(Ordering::Relaxed) {
+        verify::check_pcr_conventions(&doc).map_err(Error::generalize)?;
+    }
+    Ok((cose, doc))
+}
+
+/// Reject `input` before anything is decoded from it if it is larger than
+/// `maxInputBytes`, so a hostile input cannot make the parser allocate according
+/// to the lengths it declares. Applies to documents, roots, batches of documents,
+/// snapshots and policies alike.
+fn check_input_size(input: &[u8]) -> Result<(), Error> {
+    if input.len() > MAX_INPUT_BYTES.load(Ordering::Relaxed) {
+        return Err(Error::InputTooLarge);
+    }
+    Ok(())
+}
+
+/// Follows the AWS verification process: decode the COSE_Sign1 structure, decode the
+/// attestation document, verify the certificate chain, and check the document signature.
+/// https://docs.aws.amazon.com/enclaves/latest/user/verify-root.html
+///
+/// `roots` is a DER root certificate or a PEM bundle of them (see
+/// [`verify::parse_roots`]); the document is accepted if it chains to any. A
+/// document issued by none of them is [`Error::ChainInvalid`], while other
+/// failures are reported against the root that issued the chain.
+///
+/// The chain and signature result may come from the [`cache`], when enabled.
+/// Certificate validity windows depend on the time, so are checked on every call.
+fn r_verify(document: &[u8], roots: &[u8]) -> Result<AttestationDoc, Error> {
+    r_verify_against(document, &parse_trusted_roots(roots)?)
+}
+
+fn parse_trusted_roots(roots: &[u8]) -> Result<Vec<verify::Root>, Error> {
+    check_input_size(roots)?;
+    verify::parse_roots(roots)
+}
+
+/// [`r_verify`] against roots already parsed, so they are parsed once per batch
+fn r_verify_against(document: &[u8], roots: &[verify::Root]) -> Result<AttestationDoc, Error> {
+    let (cose, doc) = decode_document(document)?;
+    for (root_der, root) in roots {
+        match cache::get_or_verify(document, root_der, || {
+            verify::verify_chain_and_signature(&cose, &doc, root)
+        }) {
+            Ok(()) => {
+                if STRICT_CERT_VALIDITY.load(Ordering::Relaxed) {
+                    let skew = u64::from(CERT_CLOCK_SKEW_MS.load(Ordering::Relaxed));
+                    verify::check_validity(&doc, root, now_ms(), skew)?;
+                }
+                return Ok(doc);
+            }
+            // Not issued by this root, but perhaps by the next
+            Err(Error::ChainInvalid) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Err(Error::ChainInvalid)
+}
+
+#[cfg(all(test, not(feature = "nitro")))]
+mod tests {
+    use super::*;
+
+    const END_KEY: &[u8] = include_bytes!("../test_data/end-signing-key.der");
+    const TEST_VECTOR: &[u8] = include_bytes!("../test_data/test-vector.cbor");
+
+    #[test]
+    fn signing_key_der_is_scrubbed() {
+        let mut der = END_KEY.to_vec();
+        let key = parse_signing_key(&mut der).unwrap();
+        assert_eq!(key, SecretKey::from_pkcs8_der(END_KEY).unwrap());
+        assert!(der.iter().all(|byte| *byte == 0));
+
+        // Even a key that does not parse is not left behind
+        let mut der = END_KEY[..END_KEY.len() - 1].to_vec();
+        assert!(parse_signing_key(&mut der).is_err());
+        assert!(der.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn pool_requests_carry_the_request_id() {
+        let previous = replace_request_id("req-7".to_string());
+        assert_eq!(pool::run(request_id), Ok("req-7".to_string()));
+        replace_request_id(previous);
+        assert_eq!(pool::run(request_id), Ok(String::new()));
+    }
+
+    #[cfg(feature = "socket")]
+    #[test]
+    fn serves_attestations_on_a_socket() {
+        use std::io::{Read, Write};
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("deno-nitro-{}.sock", std::process::id()));
+        r_serve_socket(&path).unwrap();
+        assert_eq!(
+            r_serve_socket(&path),
+            Err(Error::InvalidArgument("already serving on a socket"))
+        );
+
+        // Restricted as soon as it is reachable, having been bound elsewhere
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let staging = path.with_file_name(format!(
+            ".deno-nitro-{}.sock.{}",
+            std::process::id(),
+            std::process::id()
+        ));
+        assert!(!staging.exists());
+
+        let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let exchange = |stream: &mut std::os::unix::net::UnixStream, request: &[u8]| {
+            stream
+                .write_all(&(request.len() as u32).to_be_bytes())
+                .unwrap();
+            stream.write_all(request).unwrap();
+            let mut header = [0; 8];
+            stream.read_exact(&mut header).unwrap();
+            let mut body = vec![0; u32::from_be_bytes(header[4..].try_into().unwrap()) as usize];
+            stream.read_exact(&mut body).unwrap();
+            (i32::from_be_bytes(header[..4].try_into().unwrap()), body)
+        };
+
+        let request = serialize::to_vec(&BTreeMap::from([
+            ("userData", ByteBuf::from(b"over the socket".to_vec())),
+            ("nonce", ByteBuf::from(vec![7; 16])),
+        ]));
+        let (status, document) = exchange(&mut stream, &request);
+        assert_eq!(status, 0);
+        let doc = r_verify(&document, &dev_root().unwrap()).unwrap();
+        assert_eq!(doc.user_data.unwrap().as_slice(), b"over the socket");
+        assert_eq!(doc.nonce.unwrap().as_slice(), [7; 16]);
+
+        // Answered on the same connection, without taking it down
+        let (status, message) = exchange(&mut stream, b"not cbor");
+        assert_eq!(status, Error::InvalidArgument("").code());
+        assert_eq!(
+            message,
+            b"invalid argument: request is not a valid CBOR map"
+        );
+        assert_eq!(exchange(&mut stream, &request).0, 0);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn supported_requests_are_answered() {
+        let driver = NITRO.as_ref().unwrap();
+        for &name in SUPPORTED_REQUESTS {
+            let request = match name {
+                "Attestation" => Request::Attestation {
+                    user_data: None,
+                    public_key: None,
+                    nonce: None,
+                },
+                "DescribeNSM" => Request::DescribeNSM,
+                "GetRandom" => Request::GetRandom,
+                name => panic!("no request to issue for {name}"),
+            };
+            let response = driver.process_request(request);
+            assert_eq!(response_kind(&response), name, "{response:?}");
+        }
+    }
+
+    #[test]
+    fn frozen_clock_decides_age() {
+        let (_, doc) = decode_document(TEST_VECTOR).unwrap();
+        let check = |max_age_ms| check_constraints(&doc, &BTreeMap::new(), &[], max_age_ms);
+        set_test_clock(TEST_VECTOR_TIMESTAMP_MS + 1_000);
+        assert_eq!(now_ms(), TEST_VECTOR_TIMESTAMP_MS + 1_000);
+        assert_eq!(check(1_000), Ok(()));
+        assert_eq!(check(999), Err(Error::PolicyViolation("document too old")));
+
+        // Timestamps ahead of the clock pass within the clock skew
+        set_test_clock(TEST_VECTOR_TIMESTAMP_MS - 60_000);
+        assert_eq!(check(1), Ok(()));
+        set_test_clock(TEST_VECTOR_TIMESTAMP_MS - 60_001);
+        assert_eq!(
+            check(u64::MAX),
+            Err(Error::PolicyViolation(
+                "document timestamp is in the future"
+            ))
+        );
+
+        set_test_clock(0);
+        assert_ne!(now_ms(), TEST_VECTOR_TIMESTAMP_MS - 60_000);
     }
 }
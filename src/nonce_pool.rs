@@ -0,0 +1,133 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Nonces drawn from the device ahead of time for `attest_with_fresh_nonce`
+//!
+//! Each `GetRandom` request is a round-trip to the NSM, so rather than making
+//! one on the critical path the pool keeps up to `size` nonces ready, and a
+//! background thread tops it up from the device whenever it falls below half
+//! full. Every nonce is handed out once, then forgotten. When the pool is empty
+//! (or off, until given a size) nonces are fetched from the device on demand.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Bytes in each nonce
+pub(crate) const NONCE_LEN: usize = 32;
+
+/// Most nonces a pool can be sized to keep (128 KiB of them), so that a typo in
+/// the size cannot have the refill thread drain the device for hours
+pub(crate) const MAX_SIZE: usize = 4096;
+
+/// Asks the device for random bytes, or `None` if it could not provide any
+pub(crate) type Fetch = fn() -> Option<Vec<u8>>;
+
+pub(crate) type Nonce = [u8; NONCE_LEN];
+
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    size: 0,
+    nonces: VecDeque::new(),
+    refilling: false,
+});
+
+struct Pool {
+    /// Most nonces kept, or zero when the pool is off
+    size: usize,
+    nonces: VecDeque<Nonce>,
+    /// Whether a refill thread is running, so only one ever is
+    refilling: bool,
+}
+
+impl Pool {
+    fn wants_refill(&self) -> bool {
+        !self.refilling && self.nonces.len() * 2 < self.size
+    }
+}
+
+fn lock() -> MutexGuard<'static, Pool> {
+    POOL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Split `random` into whole nonces
+fn nonces(random: &[u8]) -> impl Iterator<Item = Nonce> + '_ {
+    random
+        .chunks_exact(NONCE_LEN)
+        .filter_map(|chunk| chunk.try_into().ok())
+}
+
+/// Take a nonce from the pool, or from `fetch` if the pool is empty, starting a
+/// refill if that leaves it below half full
+pub(crate) fn take(fetch: Fetch) -> Option<Nonce> {
+    let pooled = {
+        let mut pool = lock();
+        let nonce = pool.nonces.pop_front();
+        if pool.wants_refill() {
+            start_refill(&mut pool, fetch);
+        }
+        nonce
+    };
+    pooled.or_else(|| nonces(&fetch()?).next())
+}
+
+/// Refill `pool` on a thread of its own, logging under the caller's request ID
+fn start_refill(pool: &mut Pool, fetch: Fetch) {
+    pool.refilling = true;
+    let request_id = crate::request_id();
+    std::thread::spawn(move || {
+        crate::replace_request_id(request_id);
+        refill(fetch);
+    });
+}
+
+/// Fetch nonces until the pool is full, or the device stops providing them
+fn refill(fetch: Fetch) {
+    loop {
+        // Fetch without holding the lock, so nonces can be taken meanwhile
+        let random = fetch();
+        let mut pool = lock();
+        let Some(random) = random else {
+            log::warn!("nonce pool refill stopped: the driver did not provide random bytes");
+            pool.refilling = false;
+            return;
+        };
+        let room = pool.size.saturating_sub(pool.nonces.len());
+        pool.nonces.extend(nonces(&random).take(room));
+        if pool.nonces.len() >= pool.size {
+            pool.refilling = false;
+            return;
+        }
+    }
+}
+
+/// Set how many nonces are kept (zero turns the pool off), dropping every pooled
+/// nonce, and start filling the pool
+pub(crate) fn configure(size: usize, fetch: Fetch) {
+    let mut pool = lock();
+    pool.size = size;
+    pool.nonces = VecDeque::new();
+    if pool.wants_refill() {
+        start_refill(&mut pool, fetch);
+    }
+}
+
+pub(crate) fn size() -> usize {
+    lock().size
+}
+
+/// Nonces ready to be taken
+pub(crate) fn pooled() -> usize {
+    lock().nonces.len()
+}
@@ -0,0 +1,119 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cache of decoded documents
+//!
+//! Callers inspecting one document with several bindings (timestamp, PCRs,
+//! digest, ...) would otherwise decode its CBOR on every call. Entries are keyed
+//! by the document's fingerprint (its SHA-256) and only hold successful decodes;
+//! when full, the least recently used entry is dropped. The cache is off until
+//! given a size.
+
+use coset::CoseSign1;
+use nsm_nitro_enclave_utils::api::nsm::AttestationDoc;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::Error;
+
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    size: 0,
+    uses: 0,
+    entries: None,
+});
+
+static HITS: AtomicU64 = AtomicU64::new(0);
+
+type Key = [u8; 32];
+
+type Decoded = (CoseSign1, AttestationDoc);
+
+struct Cache {
+    /// Most entries kept, or zero when caching is off
+    size: usize,
+    /// Lookups so far, stamped on entries to find the least recently used
+    uses: u64,
+    /// Allocated on first insert
+    entries: Option<HashMap<Key, (u64, Decoded)>>,
+}
+
+fn lock() -> MutexGuard<'static, Cache> {
+    CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Return the cached decode of `document`, or decode and cache it
+pub(crate) fn get_or_decode(
+    document: &[u8],
+    decode: impl FnOnce() -> Result<Decoded, Error>,
+) -> Result<Decoded, Error> {
+    if lock().size == 0 {
+        return decode();
+    }
+
+    let key: Key = Sha256::digest(document).into();
+    {
+        let mut cache = lock();
+        cache.uses += 1;
+        let now = cache.uses;
+        if let Some((used, decoded)) = cache
+            .entries
+            .as_mut()
+            .and_then(|entries| entries.get_mut(&key))
+        {
+            *used = now;
+            HITS.fetch_add(1, Ordering::Relaxed);
+            return Ok(decoded.clone());
+        }
+    }
+
+    // Decode without holding the lock, so other lookups can proceed
+    let decoded = decode()?;
+    let mut cache = lock();
+    let (size, now) = (cache.size, cache.uses);
+    if size != 0 {
+        let entries = cache.entries.get_or_insert_with(HashMap::new);
+        if entries.len() >= size
+            && !entries.contains_key(&key)
+            && let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(key, _)| *key)
+        {
+            entries.remove(&oldest);
+        }
+        entries.insert(key, (now, decoded.clone()));
+    }
+    Ok(decoded)
+}
+
+/// Set how many documents are kept (zero turns the cache off), dropping every
+/// cached decode
+pub(crate) fn configure(size: usize) {
+    let mut cache = lock();
+    cache.size = size;
+    cache.entries = None;
+}
+
+pub(crate) fn size() -> usize {
+    lock().size
+}
+
+/// Decodes answered from the cache since the library was loaded
+pub(crate) fn hits() -> u64 {
+    HITS.load(Ordering::Relaxed)
+}
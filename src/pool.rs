@@ -0,0 +1,166 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Bounded concurrency for `attest_async`
+//!
+//! Deno runs every non-blocking call on a thread of its own, so without a bound
+//! a burst of async attestations would all reach the NSM at once. Each one
+//! instead takes one of the pool's permits for the duration of its request,
+//! waiting for one to free up if the pool is busy. The pool size is capped at
+//! [`MAX_DEVICE_CONCURRENCY`], so oversizing it cannot oversubscribe the device.
+//!
+//! Requests run on a thread of their own while the caller waits for them, so
+//! that [`close`] can cancel the caller without waiting on a slow device. Once
+//! closed, the pool hands out no more permits; a cancelled request keeps its
+//! permit until the device answers it, and the answer is then dropped.
+
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+use crate::Error;
+
+/// Most requests ever handed to the NSM at once, whatever the pool size
+pub(crate) const MAX_DEVICE_CONCURRENCY: usize = 8;
+
+static POOL: Pool = Pool {
+    state: Mutex::new(State {
+        size: None,
+        busy: 0,
+        peak: 0,
+        closed: false,
+    }),
+    freed: Condvar::new(),
+};
+
+struct Pool {
+    state: Mutex<State>,
+    freed: Condvar,
+}
+
+struct State {
+    /// Permits, or `None` for the default
+    size: Option<usize>,
+    busy: usize,
+    /// Most permits held at once since the pool was last resized
+    peak: usize,
+    /// Set by [`close`], for the rest of the process
+    closed: bool,
+}
+
+impl State {
+    fn size(&self) -> usize {
+        self.size.unwrap_or_else(default_size)
+    }
+}
+
+/// One per CPU, up to the device's concurrency
+fn default_size() -> usize {
+    std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(MAX_DEVICE_CONCURRENCY)
+}
+
+fn lock() -> MutexGuard<'static, State> {
+    POOL.state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Run `request` once a permit is free, holding it until `request` returns. Its
+/// log lines carry the caller's request ID.
+/// Fails with `Cancelled`, without waiting for `request`, if the pool is or gets
+/// closed, or with `AttestationFailed` if `request` panics.
+pub(crate) fn run<T: Send + 'static>(
+    request: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, Error> {
+    {
+        let mut state = POOL
+            .freed
+            .wait_while(lock(), |state| !state.closed && state.busy >= state.size())
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.closed {
+            return Err(Error::Cancelled);
+        }
+        state.busy += 1;
+        state.peak = state.peak.max(state.busy);
+    }
+
+    /// Returns the permit even if `request` panics
+    struct Permit;
+    impl Drop for Permit {
+        fn drop(&mut self) {
+            lock().busy -= 1;
+            // Wakes the callers waiting for their request as well as for a permit
+            POOL.freed.notify_all();
+        }
+    }
+    let permit = Permit;
+    let (sender, receiver) = mpsc::channel();
+    let request_id = crate::request_id();
+    std::thread::spawn(move || {
+        let _permit = permit;
+        crate::replace_request_id(request_id);
+        // The caller is gone if it was cancelled
+        let _ = sender.send(request());
+    });
+    wait_for(receiver)
+}
+
+/// Wait for the result sent to `receiver`, or for the pool to be closed
+fn wait_for<T>(receiver: Receiver<T>) -> Result<T, Error> {
+    let mut result = Err(Error::Cancelled);
+    let _state = POOL
+        .freed
+        .wait_while(lock(), |state| match receiver.try_recv() {
+            Ok(value) => {
+                result = Ok(value);
+                false
+            }
+            // The request panicked, taking the sender with it
+            Err(TryRecvError::Disconnected) => {
+                result = Err(Error::AttestationFailed);
+                false
+            }
+            Err(TryRecvError::Empty) => !state.closed,
+        })
+        .unwrap_or_else(PoisonError::into_inner);
+    result
+}
+
+/// Cancel every request waiting for a permit or for the device, and refuse any
+/// more, for the rest of the process
+pub(crate) fn close() {
+    lock().closed = true;
+    POOL.freed.notify_all();
+}
+
+/// Resize the pool, capped at [`MAX_DEVICE_CONCURRENCY`], returning the size in
+/// effect. Requests already running keep their permits.
+pub(crate) fn resize(size: usize) -> usize {
+    let size = size.min(MAX_DEVICE_CONCURRENCY);
+    let mut state = lock();
+    state.size = Some(size);
+    state.peak = state.busy;
+    POOL.freed.notify_all();
+    size
+}
+
+pub(crate) fn size() -> usize {
+    lock().size()
+}
+
+/// Most requests that have run at once since the pool was last resized
+pub(crate) fn peak() -> usize {
+    lock().peak
+}
@@ -0,0 +1,126 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Cap on the `user_data` bytes attested per rolling window
+//!
+//! Coarser than the per-tag rate limits: every attestation, whoever makes it,
+//! counts its caller's `user_data` against one shared allowance of `limit` bytes
+//! over the last `window`. Each attestation's bytes are remembered until they
+//! fall out of the window, so the allowance frees up gradually rather than all
+//! at once. Bytes are counted before attesting, so concurrent callers cannot
+//! overshoot the limit, and given back with [`refund`] if the attestation then
+//! fails. The quota is off until given a limit.
+
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Length of the window when none is configured
+pub(crate) const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+static QUOTA: Mutex<Quota> = Mutex::new(Quota {
+    limit: 0,
+    window: DEFAULT_WINDOW,
+    attested: VecDeque::new(),
+    total: 0,
+});
+
+struct Quota {
+    /// Most bytes attested per window, or zero when the quota is off
+    limit: u64,
+    window: Duration,
+    /// When each attestation in the window was made, and its bytes
+    attested: VecDeque<(Instant, u64)>,
+    /// Sum of the bytes in `attested`
+    total: u64,
+}
+
+fn lock() -> MutexGuard<'static, Quota> {
+    QUOTA.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Bytes counted against the quota by [`try_take`]
+pub(crate) struct Charge {
+    at: Instant,
+    bytes: u64,
+}
+
+/// Count `bytes` against the quota, returning the charge if they fit in what is
+/// left of it (if not, nothing is counted)
+pub(crate) fn try_take(bytes: usize) -> Option<Charge> {
+    let mut quota = lock();
+    let now = Instant::now();
+    if quota.limit == 0 {
+        return Some(Charge { at: now, bytes: 0 });
+    }
+
+    while let Some(&(at, attested)) = quota.attested.front() {
+        if now.duration_since(at) < quota.window {
+            break;
+        }
+        quota.attested.pop_front();
+        quota.total -= attested;
+    }
+
+    let bytes = bytes as u64;
+    if quota.total + bytes > quota.limit {
+        return None;
+    }
+    if bytes != 0 {
+        quota.attested.push_back((now, bytes));
+        quota.total += bytes;
+    }
+    Some(Charge { at: now, bytes })
+}
+
+/// Give back a charge for an attestation that was not made, unless it has
+/// already left the window (or the quota was reconfigured since)
+pub(crate) fn refund(charge: Charge) {
+    if charge.bytes == 0 {
+        return;
+    }
+    let mut quota = lock();
+    if let Some(index) = quota
+        .attested
+        .iter()
+        .rposition(|&(at, bytes)| at == charge.at && bytes == charge.bytes)
+    {
+        quota.attested.remove(index);
+        quota.total -= charge.bytes;
+    }
+}
+
+/// Set the most bytes attested per window (zero turns the quota off) and the
+/// window's length, forgetting what has been attested so far
+pub(crate) fn configure(limit: Option<u64>, window: Option<Duration>) {
+    let mut quota = lock();
+    if let Some(limit) = limit {
+        quota.limit = limit;
+    }
+    if let Some(window) = window {
+        quota.window = window;
+    }
+    quota.attested = VecDeque::new();
+    quota.total = 0;
+}
+
+pub(crate) fn limit() -> u64 {
+    lock().limit
+}
+
+pub(crate) fn window() -> Duration {
+    lock().window
+}
@@ -0,0 +1,116 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Per-tag token buckets for `attest_tagged`
+//!
+//! Each caller tag gets a bucket of `capacity` tokens, refilled continuously at
+//! `refill_per_second`; an attestation takes one token, and is refused when the
+//! bucket is empty. A bucket that has refilled completely is indistinguishable
+//! from a new one, so full buckets are dropped once there are many tags. Rate
+//! limiting is off until given a capacity.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+/// Tokens added to each bucket per second when no rate is configured
+pub(crate) const DEFAULT_REFILL_PER_SECOND: u32 = 1;
+
+/// Buckets kept before full ones are dropped
+const PRUNE_THRESHOLD: usize = 4096;
+
+static LIMITER: Mutex<Limiter> = Mutex::new(Limiter {
+    capacity: 0,
+    refill_per_second: DEFAULT_REFILL_PER_SECOND,
+    buckets: None,
+});
+
+struct Limiter {
+    /// Most tokens a bucket holds, or zero when rate limiting is off
+    capacity: u32,
+    refill_per_second: u32,
+    /// Allocated on first use
+    buckets: Option<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    /// When `tokens` was last brought up to date
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, capacity: f64, refill_per_second: f64) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill_per_second).min(capacity);
+        self.updated = now;
+    }
+}
+
+fn lock() -> MutexGuard<'static, Limiter> {
+    LIMITER.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Take a token from `tag`'s bucket, returning whether there was one
+pub(crate) fn try_take(tag: &str) -> bool {
+    let mut limiter = lock();
+    if limiter.capacity == 0 {
+        return true;
+    }
+
+    let now = Instant::now();
+    let capacity = f64::from(limiter.capacity);
+    let refill_per_second = f64::from(limiter.refill_per_second);
+    let buckets = limiter.buckets.get_or_insert_with(HashMap::new);
+    if buckets.len() >= PRUNE_THRESHOLD && !buckets.contains_key(tag) {
+        buckets.retain(|_, bucket| {
+            bucket.refill(now, capacity, refill_per_second);
+            bucket.tokens < capacity
+        });
+    }
+
+    let bucket = buckets.entry(tag.to_owned()).or_insert(Bucket {
+        tokens: capacity,
+        updated: now,
+    });
+    bucket.refill(now, capacity, refill_per_second);
+    if bucket.tokens < 1.0 {
+        return false;
+    }
+    bucket.tokens -= 1.0;
+    true
+}
+
+/// Set the bucket capacity (zero turns rate limiting off) and refill rate,
+/// refilling every bucket
+pub(crate) fn configure(capacity: Option<u32>, refill_per_second: Option<u32>) {
+    let mut limiter = lock();
+    if let Some(capacity) = capacity {
+        limiter.capacity = capacity;
+    }
+    if let Some(refill_per_second) = refill_per_second {
+        limiter.refill_per_second = refill_per_second;
+    }
+    limiter.buckets = None;
+}
+
+pub(crate) fn capacity() -> u32 {
+    lock().capacity
+}
+
+pub(crate) fn refill_per_second() -> u32 {
+    lock().refill_per_second
+}
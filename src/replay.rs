@@ -0,0 +1,120 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Nonces already accepted by `verify_with_policy`, so each is accepted once
+//!
+//! Nonces are remembered by their SHA-256 for `ttl` after they are accepted, up
+//! to `capacity` of them; when full, the oldest is forgotten early to make room,
+//! so the capacity should cover every nonce accepted within the TTL (and the TTL
+//! the oldest document a policy accepts). The set is off until given a capacity.
+
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long nonces are remembered when no TTL is configured
+pub(crate) const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+static SEEN: Mutex<Seen> = Mutex::new(Seen {
+    capacity: 0,
+    ttl: DEFAULT_TTL,
+    order: VecDeque::new(),
+    nonces: None,
+});
+
+type Key = [u8; 32];
+
+struct Seen {
+    /// Most nonces remembered, or zero when replay protection is off
+    capacity: usize,
+    ttl: Duration,
+    /// When each remembered nonce was accepted, oldest first
+    order: VecDeque<(Instant, Key)>,
+    /// The nonces in `order`, allocated on first insert
+    nonces: Option<HashSet<Key>>,
+}
+
+impl Seen {
+    fn forget_expired(&mut self, now: Instant) {
+        while let Some(&(at, key)) = self.order.front() {
+            if now.duration_since(at) < self.ttl {
+                break;
+            }
+            self.order.pop_front();
+            if let Some(nonces) = &mut self.nonces {
+                nonces.remove(&key);
+            }
+        }
+    }
+}
+
+fn lock() -> MutexGuard<'static, Seen> {
+    SEEN.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Whether `nonce` has not been accepted yet (always, when replay protection is
+/// off), remembering it as accepted from now on if so and `consume` is set
+pub(crate) fn first_use(nonce: &[u8], consume: bool) -> bool {
+    let mut seen = lock();
+    if seen.capacity == 0 {
+        return true;
+    }
+
+    let now = Instant::now();
+    seen.forget_expired(now);
+    let key: Key = Sha256::digest(nonce).into();
+    if seen
+        .nonces
+        .as_ref()
+        .is_some_and(|nonces| nonces.contains(&key))
+    {
+        return false;
+    }
+    if consume {
+        if seen.order.len() >= seen.capacity
+            && let Some((_, oldest)) = seen.order.pop_front()
+            && let Some(nonces) = &mut seen.nonces
+        {
+            nonces.remove(&oldest);
+        }
+        seen.order.push_back((now, key));
+        seen.nonces.get_or_insert_with(HashSet::new).insert(key);
+    }
+    true
+}
+
+/// Set how many nonces are remembered (zero turns replay protection off) and
+/// for how long, forgetting every one accepted so far
+pub(crate) fn configure(capacity: Option<usize>, ttl: Option<Duration>) {
+    let mut seen = lock();
+    if let Some(capacity) = capacity {
+        seen.capacity = capacity;
+    }
+    if let Some(ttl) = ttl {
+        seen.ttl = ttl;
+    }
+    seen.order = VecDeque::new();
+    seen.nonces = None;
+}
+
+pub(crate) fn capacity() -> usize {
+    lock().capacity
+}
+
+pub(crate) fn ttl() -> Duration {
+    lock().ttl
+}
@@ -0,0 +1,52 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! The one wire format for structs crossing the FFI boundary
+//!
+//! Every binding that takes or returns a struct uses CBOR (RFC 8949), the same
+//! encoding as attestation documents themselves, via [`to_vec`] and
+//! [`from_slice`]. Structs are maps keyed by their camelCase field names, with
+//! definite lengths and integers in their shortest form. CBOR fixes the byte
+//! order of every value (big-endian), so the JS side decodes the same bytes the
+//! same way on any architecture. Byte strings stay binary rather than being
+//! base64 encoded as they would be in JSON.
+
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Encode `value` as CBOR
+pub(crate) fn to_vec<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    // Writing to a Vec cannot fail, and our structs only have string keys
+    if let Err(err) = ciborium::into_writer(value, &mut out) {
+        log::error!("failed to encode struct: {err:?}");
+        out.clear();
+    }
+    out
+}
+
+/// Decode a `T` from CBOR making up the whole of `bytes`, describing why on failure
+pub(crate) fn from_slice<T: DeserializeOwned>(mut bytes: &[u8]) -> Result<T, String> {
+    let value = ciborium::from_reader(&mut bytes).map_err(|err| match err {
+        ciborium::de::Error::Semantic(_, reason) => reason,
+        ciborium::de::Error::Syntax(offset) => format!("invalid CBOR at byte {offset}"),
+        ciborium::de::Error::Io(_) => "truncated CBOR".to_string(),
+        ciborium::de::Error::RecursionLimitExceeded => "CBOR nested too deeply".to_string(),
+    })?;
+    if !bytes.is_empty() {
+        return Err("trailing bytes after CBOR value".to_string());
+    }
+    Ok(value)
+}
@@ -0,0 +1,213 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Attestation service on a Unix domain socket, for processes on the same host
+//! that cannot load the bindings
+//!
+//! A connection carries any number of requests, each answered before the next
+//! is read. A request is a 4 byte big-endian length, then that many bytes of
+//! CBOR: a map with the optional byte strings `userData`, `publicKey` and
+//! `nonce`, attested as given, and an optional text `requestId` to tag the
+//! request's log lines with. The response is a 4 byte big-endian status (0,
+//! or an `ErrorCode`), a 4 byte big-endian length, then that many bytes: the
+//! document, or why there is none in UTF-8. A request longer than
+//! `maxInputBytes` is answered with `InputTooLarge` and its connection closed,
+//! so the rest of it is never read.
+//!
+//! Anyone who can connect gets documents carrying data of their choosing, so the
+//! socket is only accessible to the user running the enclave process (mode
+//! 0600). It is bound in a new directory only that user can enter, restricted,
+//! then moved into place, so nobody else can connect before it is restricted.
+//! Every connection has its own thread, up to [`MAX_CONNECTIONS`] at once,
+//! and is closed unless each whole request arrives within [`REQUEST_TIMEOUT`]
+//! (and each response is taken within it), however the bytes trickle in.
+
+use nsm_nitro_enclave_utils::api::ByteBuf;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::Error;
+
+/// One attestation request, as sent over the socket
+#[derive(serde::Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub(crate) struct AttestRequest {
+    pub user_data: Option<ByteBuf>,
+    pub public_key: Option<ByteBuf>,
+    pub nonce: Option<ByteBuf>,
+    /// Tags the log lines of this request, as `set_request_id` does
+    pub request_id: Option<String>,
+}
+
+/// Answers a request's CBOR with a document
+pub(crate) type Attest = fn(&[u8]) -> Result<Vec<u8>, Error>;
+
+/// Most connections served at once; any more are closed as soon as accepted
+pub(crate) const MAX_CONNECTIONS: usize = 64;
+
+/// How long a connection has to send each whole request, counted from when the
+/// previous one was answered, and to take each response, before it is closed
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// One of the [`MAX_CONNECTIONS`], given back when dropped
+struct Connection;
+
+impl Connection {
+    fn open() -> Option<Self> {
+        CONNECTIONS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < MAX_CONNECTIONS).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Connection)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Listen on `path`, replacing a socket left there by an earlier process (but
+/// nothing else), and serve every connection on its own thread with `attest`
+pub(crate) fn serve(path: &Path, attest: Attest) -> io::Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = bind_restricted(path)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let Some(connection) = Connection::open() else {
+                        log::warn!(
+                            "refused an attestation socket connection: {MAX_CONNECTIONS} already open"
+                        );
+                        continue;
+                    };
+                    std::thread::spawn(move || {
+                        let _connection = connection;
+                        if let Err(err) = handle(stream, attest) {
+                            log::debug!("attestation socket connection closed: {err}");
+                        }
+                    });
+                }
+                Err(err) => log::warn!("failed to accept an attestation socket connection: {err}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Bind a socket at `path` with mode 0600, never reachable by anyone else
+fn bind_restricted(path: &Path) -> io::Result<UnixListener> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "socket path has no file name")
+    })?;
+    let mut staging = PathBuf::from(path.parent().unwrap_or(Path::new(".")));
+    staging.push(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    std::fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let staged = staging.join(name);
+    let bound = UnixListener::bind(&staged).and_then(|listener| {
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&staging);
+    bound
+}
+
+/// Answer requests on `stream` until the caller closes it
+fn handle(mut stream: UnixStream, attest: Attest) -> io::Result<()> {
+    loop {
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        let mut len = [0; 4];
+        if !read_by(&mut stream, &mut len, deadline)? {
+            return Ok(());
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > crate::MAX_INPUT_BYTES.load(Ordering::Relaxed) {
+            return respond(&mut stream, Err(Error::InputTooLarge));
+        }
+        let mut request = vec![0; len];
+        if !read_by(&mut stream, &mut request, deadline)? {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        respond(&mut stream, attest(&request))?;
+    }
+}
+
+/// The time left until `deadline`, or a timeout error once it has passed
+fn left_until(deadline: Instant) -> io::Result<Duration> {
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => Ok(left),
+        _ => Err(io::ErrorKind::TimedOut.into()),
+    }
+}
+
+/// Fill `buf` from `stream` by `deadline`, returning false if the caller closed
+/// the connection before sending any of it
+fn read_by(stream: &mut UnixStream, buf: &mut [u8], deadline: Instant) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        stream.set_read_timeout(Some(left_until(deadline)?))?;
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(true)
+}
+
+fn respond(stream: &mut UnixStream, outcome: Result<Vec<u8>, Error>) -> io::Result<()> {
+    let (status, body) = match outcome {
+        Ok(document) => (0, document),
+        Err(err) => (err.code(), err.to_string().into_bytes()),
+    };
+    let mut response = Vec::with_capacity(8 + body.len());
+    response.extend_from_slice(&status.to_be_bytes());
+    response.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    response.extend_from_slice(&body);
+
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    let mut written = 0;
+    while written < response.len() {
+        stream.set_write_timeout(Some(left_until(deadline)?))?;
+        match stream.write(&response[written..]) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(wrote) => written += wrote,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Attestation counters and latency quantiles, as reported by `stats`
+//!
+//! Latencies are summarised with the P² algorithm (Jain & Chlamtac, 1985),
+//! which tracks each quantile in five markers, so recording a request costs a
+//! few comparisons and no allocation however many requests are seen.
+
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::Duration;
+
+static RECORDER: Mutex<Recorder> = Mutex::new(Recorder::new());
+
+/// How long setting up the driver took, once it has been
+static DRIVER_INIT: OnceLock<Duration> = OnceLock::new();
+
+/// Counters and latency quantiles since the library was loaded
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Stats {
+    /// Attestation requests sent to the driver
+    pub attestations: u64,
+    /// Requests the driver did not answer with a document
+    pub failures: u64,
+    /// Most `attest_async` requests run at once since the pool was last resized
+    pub peak_async_attestations: u64,
+    /// Verifications whose chain and signature result came from the cache
+    pub verification_cache_hits: u64,
+    /// Decodes reused from the parsed document cache
+    pub document_cache_hits: u64,
+    /// How long the driver took to set up (successfully or not), or 0 before it is
+    pub driver_init_micros: u64,
+    /// Nonces ready in the pool for `attest_with_fresh_nonce`
+    pub pooled_nonces: u64,
+    pub latency_micros: Latency,
+}
+
+/// Estimated request latency quantiles and the exact maximum, in microseconds
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Latency {
+    pub p50: u64,
+    pub p95: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// Record one attestation request that took `elapsed`
+pub(crate) fn record(elapsed: Duration, ok: bool) {
+    RECORDER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .record(elapsed.as_micros() as f64, ok);
+}
+
+/// Record how long the driver took to set up; only the first call counts
+pub(crate) fn record_driver_init(elapsed: Duration) {
+    let _ = DRIVER_INIT.set(elapsed);
+}
+
+pub(crate) fn current() -> Stats {
+    RECORDER
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .stats()
+}
+
+struct Recorder {
+    attestations: u64,
+    failures: u64,
+    p50: P2,
+    p95: P2,
+    p99: P2,
+    max: f64,
+}
+
+impl Recorder {
+    const fn new() -> Self {
+        Self {
+            attestations: 0,
+            failures: 0,
+            p50: P2::new(0.5),
+            p95: P2::new(0.95),
+            p99: P2::new(0.99),
+            max: 0.0,
+        }
+    }
+
+    fn record(&mut self, micros: f64, ok: bool) {
+        self.attestations += 1;
+        if !ok {
+            self.failures += 1;
+        }
+        self.p50.observe(micros);
+        self.p95.observe(micros);
+        self.p99.observe(micros);
+        self.max = self.max.max(micros);
+    }
+
+    fn stats(&self) -> Stats {
+        // Each quantile is estimated independently, so clamp them into order
+        let p99 = self.p99.estimate().min(self.max);
+        let p95 = self.p95.estimate().min(p99);
+        let p50 = self.p50.estimate().min(p95);
+        Stats {
+            attestations: self.attestations,
+            failures: self.failures,
+            peak_async_attestations: crate::pool::peak() as u64,
+            verification_cache_hits: crate::cache::hits(),
+            document_cache_hits: crate::parsed::hits(),
+            // At least 1 once set, so a fast set-up is not mistaken for none
+            driver_init_micros: DRIVER_INIT
+                .get()
+                .map_or(0, |elapsed| (elapsed.as_micros() as u64).max(1)),
+            pooled_nonces: crate::nonce_pool::pooled() as u64,
+            latency_micros: Latency {
+                p50: p50 as u64,
+                p95: p95 as u64,
+                p99: p99 as u64,
+                max: self.max as u64,
+            },
+        }
+    }
+}
+
+/// Streaming estimate of the `p` quantile
+struct P2 {
+    p: f64,
+    /// Observations so far, the first five of which seed the markers
+    count: usize,
+    /// Marker heights
+    heights: [f64; 5],
+    /// Actual marker positions
+    positions: [f64; 5],
+    /// Desired marker positions, and how far each moves per observation
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2 {
+    const fn new(p: f64) -> Self {
+        Self {
+            p,
+            count: 0,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+        }
+    }
+
+    fn observe(&mut self, x: f64) {
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        // Find the cell x falls in, extending the extremes if needed
+        let h = &mut self.heights;
+        let cell = if x < h[0] {
+            h[0] = x;
+            0
+        } else if x >= h[4] {
+            h[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| x < h[i + 1]).unwrap_or(3)
+        };
+        for position in &mut self.positions[cell + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        // Nudge the middle markers towards their desired positions
+        for i in 1..4 {
+            let n = self.positions;
+            let drift = self.desired[i] - n[i];
+            if (drift >= 1.0 && n[i + 1] - n[i] > 1.0) || (drift <= -1.0 && n[i - 1] - n[i] < -1.0)
+            {
+                let d = drift.signum();
+                let parabolic = h[i]
+                    + d / (n[i + 1] - n[i - 1])
+                        * ((n[i] - n[i - 1] + d) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                            + (n[i + 1] - n[i] - d) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]));
+                h[i] = if h[i - 1] < parabolic && parabolic < h[i + 1] {
+                    parabolic
+                } else {
+                    let j = if d > 0.0 { i + 1 } else { i - 1 };
+                    h[i] + d * (h[j] - h[i]) / (n[j] - n[i])
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn estimate(&self) -> f64 {
+        if self.count >= 5 {
+            return self.heights[2];
+        }
+        if self.count == 0 {
+            return 0.0;
+        }
+        // Too few observations for the markers, so use the exact quantile
+        let mut seen = self.heights;
+        let seen = &mut seen[..self.count];
+        seen.sort_by(f64::total_cmp);
+        seen[((self.count - 1) as f64 * self.p).round() as usize]
+    }
+}
@@ -0,0 +1,600 @@
+/*!
+ * Copyright 2025 Qlever LLC
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Parsing and verification of attestation documents
+//!
+//! Everything here is a pure function of its arguments: there is no FFI, driver,
+//! configuration or clock (callers pass the time in), so it can be lifted into
+//! another crate, or a constrained target, along with [`Error`]. The bindings in
+//! the crate root add the caching, options and error reporting around it.
+
+use ciborium_ll::{Decoder, Header};
+use coset::{CborSerializable, CoseSign1, iana};
+use nsm_nitro_enclave_utils::api::nsm::{AttestationDoc, Digest};
+use p384::ecdsa::{DerSignature, Signature, VerifyingKey, signature::Verifier};
+use p384::pkcs8::EncodePublicKey;
+use x509_cert::{
+    Certificate,
+    der::{Decode, Encode},
+    spki::SubjectPublicKeyInfoRef,
+};
+
+use crate::Error;
+
+/// Parse a trusted root certificate from DER
+pub(crate) fn parse_root(root_der: &[u8]) -> Result<Certificate, Error> {
+    Certificate::from_der(root_der).map_err(|_| Error::InvalidRoot)
+}
+
+/// A trusted root and its DER encoding
+pub(crate) type Root = (Vec<u8>, Certificate);
+
+/// Parse trusted roots: a DER certificate, or a PEM bundle of one or more (e.g.
+/// the roots of several AWS partitions), every entry of which must parse
+pub(crate) fn parse_roots(roots: &[u8]) -> Result<Vec<Root>, Error> {
+    if !roots.trim_ascii_start().starts_with(b"-----BEGIN") {
+        return Ok(vec![(roots.to_vec(), parse_root(roots)?)]);
+    }
+    let bundle = Certificate::load_pem_chain(roots).map_err(|_| Error::InvalidRoot)?;
+    if bundle.is_empty() {
+        return Err(Error::InvalidRoot);
+    }
+    bundle
+        .into_iter()
+        .map(|root| Ok((root.to_der().map_err(|_| Error::InvalidRoot)?, root)))
+        .collect()
+}
+
+/// COSE algorithm (RFC 9054) of the hash a document's PCRs were computed with
+pub(crate) fn digest_algorithm(digest: Digest) -> iana::Algorithm {
+    match digest {
+        Digest::SHA256 => iana::Algorithm::SHA_256,
+        Digest::SHA384 => iana::Algorithm::SHA_384,
+        Digest::SHA512 => iana::Algorithm::SHA_512,
+    }
+}
+
+/// Most PCRs an NSM reports, which are indexed below it
+pub(crate) const MAX_PCRS: usize = 32;
+
+/// How a document's PCRs break the AWS Nitro conventions, each reported with a
+/// code of its own by `validate_pcr_banks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PcrAnomaly {
+    /// None, or more than [`MAX_PCRS`]
+    Count,
+    /// An index of [`MAX_PCRS`] or more
+    IndexOutOfRange,
+    /// An index given twice
+    DuplicateIndex,
+    /// A PCR that is not the length of a hash of the declared digest
+    LengthMismatch,
+}
+
+impl PcrAnomaly {
+    pub(crate) fn reason(self) -> &'static str {
+        match self {
+            PcrAnomaly::Count => "unexpected number of PCRs",
+            PcrAnomaly::IndexOutOfRange => "PCR index out of range",
+            PcrAnomaly::DuplicateIndex => "duplicate PCR index",
+            PcrAnomaly::LengthMismatch => "PCR length does not match the digest",
+        }
+    }
+}
+
+/// Check `doc`'s PCRs follow the AWS Nitro conventions: at least one and at most
+/// [`MAX_PCRS`] of them, indexed below [`MAX_PCRS`], each the length of a hash
+/// of the declared digest. Signatures cover whatever PCRs a module reports, so
+/// this is policy, for modules known to follow the conventions.
+pub(crate) fn check_pcr_conventions(doc: &AttestationDoc) -> Result<(), Error> {
+    if doc.pcrs.is_empty() || doc.pcrs.len() > MAX_PCRS {
+        return Err(Error::PcrAnomaly(PcrAnomaly::Count));
+    }
+    if doc.pcrs.keys().any(|&index| index >= MAX_PCRS) {
+        return Err(Error::PcrAnomaly(PcrAnomaly::IndexOutOfRange));
+    }
+    let len = match doc.digest {
+        Digest::SHA256 => 32,
+        Digest::SHA384 => 48,
+        Digest::SHA512 => 64,
+    };
+    if doc.pcrs.values().any(|pcr| pcr.len() != len) {
+        return Err(Error::PcrAnomaly(PcrAnomaly::LengthMismatch));
+    }
+    Ok(())
+}
+
+/// Check the leaf and intermediates of a verified document's chain are within
+/// their validity windows at `now_ms`, stretched by `skew_ms` either side. The
+/// trusted root is the caller's choice, so is not checked.
+pub(crate) fn check_validity(
+    doc: &AttestationDoc,
+    root: &Certificate,
+    now_ms: u64,
+    skew_ms: u64,
+) -> Result<(), Error> {
+    let (leaf, intermediates) = chain_certificates(doc, root)?;
+    for cert in intermediates.iter().chain([&leaf]) {
+        let validity = &cert.tbs_certificate.validity;
+        let not_before = validity.not_before.to_unix_duration().as_millis() as u64;
+        let not_after = validity.not_after.to_unix_duration().as_millis() as u64;
+        if now_ms.saturating_add(skew_ms) < not_before {
+            return Err(Error::CertNotYetValid);
+        }
+        if now_ms.saturating_sub(skew_ms) > not_after {
+            return Err(Error::CertExpired);
+        }
+    }
+    Ok(())
+}
+
+/// Parse the leaf certificate of `doc`, and the intermediates leading from `root`
+/// to it. The NSM orders the cabundle root first, ending with the leaf's issuer,
+/// and includes the root itself, which is only trusted if it is ours.
+fn chain_certificates(
+    doc: &AttestationDoc,
+    root: &Certificate,
+) -> Result<(Certificate, Vec<Certificate>), Error> {
+    let leaf = Certificate::from_der(&doc.certificate)
+        .map_err(|_| Error::MalformedDocument("invalid certificate"))?;
+    let mut cabundle = doc
+        .cabundle
+        .iter()
+        .map(|der| Certificate::from_der(der))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Error::MalformedDocument("invalid cabundle"))?;
+    if cabundle.first() == Some(root) {
+        cabundle.remove(0);
+    }
+    Ok((leaf, cabundle))
+}
+
+/// Verify `doc`'s certificate chain runs from `root` to its leaf certificate, and
+/// that the leaf's key signed `cose` with ES384. A chain whose top was not issued
+/// by `root` is [`Error::ChainInvalid`]; one that fails further down, so that the
+/// cabundle does not connect to the leaf, is [`Error::ChainBroken`].
+pub(crate) fn verify_chain_and_signature(
+    cose: &CoseSign1,
+    doc: &AttestationDoc,
+    root: &Certificate,
+) -> Result<(), Error> {
+    let es384 = coset::Algorithm::Assigned(iana::Algorithm::ES384);
+    if cose.protected.header.alg != Some(es384) {
+        return Err(Error::MalformedDocument("not signed with ES384"));
+    }
+
+    // The first link ties the chain to the trusted root; every later one must
+    // join it up to the leaf without gaps
+    let (leaf, intermediates) = chain_certificates(doc, root)?;
+    let mut issuer = root;
+    for (link, cert) in intermediates.iter().chain([&leaf]).enumerate() {
+        verify_issued_by(cert, issuer).map_err(|err| match link {
+            0 => err,
+            _ => Error::ChainBroken,
+        })?;
+        issuer = cert;
+    }
+
+    let key = public_key(&leaf).ok_or(Error::SignatureInvalid)?;
+    cose.verify_signature(b"", |signature, data| {
+        let signature = Signature::from_slice(signature)?;
+        key.verify(data, &signature)
+    })
+    .map_err(|_| Error::SignatureInvalid)
+}
+
+/// Decode the COSE_Sign1 structure and its attestation document payload, without verifying anything
+pub(crate) fn decode_document(document: &[u8]) -> Result<(CoseSign1, AttestationDoc), Error> {
+    let cose = CoseSign1::from_slice(document)
+        .map_err(|_| Error::MalformedDocument("not a COSE_Sign1 structure"))?;
+    let payload = cose
+        .payload
+        .as_deref()
+        .ok_or(Error::MalformedDocument("missing payload"))?;
+    check_payload_fields(payload)?;
+    let doc = AttestationDoc::from_binary(payload)
+        .map_err(|_| Error::MalformedDocument("payload is not an attestation document"))?;
+
+    Ok((cose, doc))
+}
+
+/// Fields every NSM attestation document carries, with the reason reported when one is absent
+const MANDATORY_FIELDS: [(&str, &str); 6] = [
+    ("module_id", "missing module_id"),
+    ("digest", "missing digest"),
+    ("timestamp", "missing timestamp"),
+    ("pcrs", "missing pcrs"),
+    ("certificate", "missing certificate"),
+    ("cabundle", "missing cabundle"),
+];
+
+/// Deepest nesting of CBOR arrays, maps and tags accepted in a payload
+const MAX_PAYLOAD_DEPTH: usize = 16;
+
+/// Reject a payload whose top-level map omits a mandatory field or repeats a key,
+/// or whose `pcrs` map repeats an index. Deserializing alone would let a later
+/// duplicate silently replace an earlier value.
+fn check_payload_fields(mut payload: &[u8]) -> Result<(), Error> {
+    let input = &mut payload;
+    let mut seen = Vec::new();
+    for_each_entry(input, |key, input| {
+        let Header::Text(Some(len)) = key else {
+            return Err(Error::MalformedDocument(
+                "payload keys must be text strings",
+            ));
+        };
+        let key = take_text(input, len)?;
+        if seen.contains(&key) {
+            return Err(Error::MalformedDocument("duplicate field in payload"));
+        }
+        seen.push(key);
+        if key == b"pcrs" {
+            check_pcr_indices(input)
+        } else {
+            skip_value(input, 0)
+        }
+    })?;
+    if !input.is_empty() {
+        return Err(Error::MalformedDocument("trailing bytes after payload"));
+    }
+
+    for (field, missing) in MANDATORY_FIELDS {
+        if !seen.contains(&field.as_bytes()) {
+            return Err(Error::MalformedDocument(missing));
+        }
+    }
+    Ok(())
+}
+
+/// Check the `pcrs` map is keyed by distinct integers
+fn check_pcr_indices(input: &mut &[u8]) -> Result<(), Error> {
+    let mut seen = Vec::new();
+    for_each_entry(input, |key, input| {
+        let Header::Positive(index) = key else {
+            return Err(Error::MalformedDocument("PCR indices must be integers"));
+        };
+        if seen.contains(&index) {
+            return Err(Error::PcrAnomaly(PcrAnomaly::DuplicateIndex));
+        }
+        seen.push(index);
+        skip_value(input, 1)
+    })
+}
+
+/// Read a CBOR map from the front of `input`, calling `entry` with each key's
+/// header and the input positioned after it; `entry` must consume the key's
+/// content (if any) and its value.
+fn for_each_entry<'a>(
+    input: &mut &'a [u8],
+    mut entry: impl FnMut(Header, &mut &'a [u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match pull_document_header(input)? {
+        Header::Map(Some(len)) => {
+            for _ in 0..len {
+                let key = pull_document_header(input)?;
+                entry(key, input)?;
+            }
+        }
+        Header::Map(None) => loop {
+            match pull_document_header(input)? {
+                Header::Break => break,
+                key => entry(key, input)?,
+            }
+        },
+        _ => return Err(Error::MalformedDocument("expected a CBOR map")),
+    }
+    Ok(())
+}
+
+/// Skip one complete CBOR value at the front of `input`
+fn skip_value(input: &mut &[u8], depth: usize) -> Result<(), Error> {
+    let header = pull_document_header(input)?;
+    skip_content(header, input, depth)
+}
+
+/// Skip whatever follows `header` to complete its value
+fn skip_content(header: Header, input: &mut &[u8], depth: usize) -> Result<(), Error> {
+    if depth > MAX_PAYLOAD_DEPTH {
+        return Err(Error::MalformedDocument("payload nested too deeply"));
+    }
+    match header {
+        Header::Bytes(Some(len)) => take(input, len).map(drop),
+        Header::Text(Some(len)) => take_text(input, len).map(drop),
+        Header::Array(Some(len)) => (0..len).try_for_each(|_| skip_value(input, depth + 1)),
+        Header::Map(Some(len)) => (0..len * 2).try_for_each(|_| skip_value(input, depth + 1)),
+        Header::Tag(_) => skip_value(input, depth + 1),
+        // Indefinite lengths: segments, items or key/value pairs up to a break
+        Header::Bytes(None) | Header::Text(None) | Header::Array(None) | Header::Map(None) => {
+            loop {
+                match pull_document_header(input)? {
+                    Header::Break => return Ok(()),
+                    item => skip_content(item, input, depth + 1)?,
+                }
+            }
+        }
+        Header::Break => Err(Error::MalformedDocument("unexpected CBOR break")),
+        Header::Positive(_) | Header::Negative(_) | Header::Float(_) | Header::Simple(_) => Ok(()),
+    }
+}
+
+/// Split `len` bytes off the front of `input`
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    if len > input.len() {
+        return Err(Error::MalformedDocument("truncated payload"));
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+    Ok(taken)
+}
+
+/// Split a text string of `len` bytes off the front of `input`, which must be
+/// valid UTF-8 so that nothing downstream has to guess at its characters
+fn take_text<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    let text = take(input, len)?;
+    std::str::from_utf8(text)
+        .map_err(|_| Error::MalformedDocument("invalid UTF-8 in payload text"))?;
+    Ok(text)
+}
+
+fn pull_document_header(input: &mut &[u8]) -> Result<Header, Error> {
+    Decoder::from(input)
+        .pull()
+        .map_err(|_| Error::MalformedDocument("invalid CBOR in payload"))
+}
+
+/// Check `cert` carries a valid ecdsa-with-SHA384 signature by `issuer`'s key
+pub(crate) fn verify_issued_by(cert: &Certificate, issuer: &Certificate) -> Result<(), Error> {
+    if cert.signature_algorithm.oid != x509_cert::der::oid::db::rfc5912::ECDSA_WITH_SHA_384 {
+        return Err(Error::ChainInvalid);
+    }
+    let key = public_key(issuer).ok_or(Error::ChainInvalid)?;
+    let tbs = cert
+        .tbs_certificate
+        .to_der()
+        .map_err(|_| Error::ChainInvalid)?;
+    let signature =
+        DerSignature::from_bytes(cert.signature.raw_bytes()).map_err(|_| Error::ChainInvalid)?;
+
+    key.verify(&tbs, &signature)
+        .map_err(|_| Error::ChainInvalid)
+}
+
+/// DER SubjectPublicKeyInfo of the key in `doc`'s leaf certificate, the one
+/// that signed the document
+pub(crate) fn leaf_public_key(doc: &AttestationDoc) -> Result<Vec<u8>, Error> {
+    Certificate::from_der(&doc.certificate)
+        .and_then(|leaf| leaf.tbs_certificate.subject_public_key_info.to_der())
+        .map_err(|_| Error::MalformedDocument("invalid certificate"))
+}
+
+/// `key`, as bound into a document's `public_key`, as a DER SubjectPublicKeyInfo
+/// importable anywhere (e.g. WebCrypto's `spki` format): an SPKI is re-encoded
+/// as canonical DER, and a bare SEC1 P-384 point (compressed or not) wrapped in
+/// one. Anything else is not a key this can vouch for the encoding of.
+pub(crate) fn normalize_public_key(key: &[u8]) -> Result<Vec<u8>, Error> {
+    if let Ok(spki) = SubjectPublicKeyInfoRef::from_der(key) {
+        return spki
+            .to_der()
+            .map_err(|_| Error::MalformedDocument("invalid public key"));
+    }
+    p384::PublicKey::from_sec1_bytes(key)
+        .ok()
+        .and_then(|key| key.to_public_key_der().ok())
+        .map(|der| der.into_vec())
+        .ok_or(Error::MalformedDocument(
+            "public key is neither an SPKI nor a P-384 point",
+        ))
+}
+
+/// P-384 public key of `cert`, if it has one
+fn public_key(cert: &Certificate) -> Option<VerifyingKey> {
+    let spki = &cert.tbs_certificate.subject_public_key_info;
+    VerifyingKey::from_sec1_bytes(spki.subject_public_key.as_bytes()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coset::{CoseSign1Builder, HeaderBuilder};
+    use p384::ecdsa::{SigningKey, signature::Signer};
+    use p384::pkcs8::DecodePrivateKey;
+    use std::collections::BTreeMap;
+
+    const ROOT: &[u8] = include_bytes!("../test_data/expired/root-certificate.der");
+    const INT: &[u8] = include_bytes!("../test_data/expired/int-certificate.der");
+    const END: &[u8] = include_bytes!("../test_data/expired/end-certificate.der");
+    const END_KEY: &[u8] = include_bytes!("../test_data/expired/end-signing-key.der");
+    const OTHER_ROOT: &[u8] = include_bytes!("../test_data/not-yet-valid/root-certificate.der");
+
+    /// 2000-06-01, while the `expired` test PKI was valid (all of 2000)
+    const JUNE_2000_MS: u64 = 959_817_600_000;
+    const DAY_MS: u64 = 24 * 3_600_000;
+
+    fn payload(cabundle: &[&[u8]]) -> Vec<u8> {
+        payload_with_pcrs(cabundle, BTreeMap::from([(0, vec![0; 48])]))
+    }
+
+    fn payload_with_pcrs(cabundle: &[&[u8]], pcrs: BTreeMap<usize, Vec<u8>>) -> Vec<u8> {
+        AttestationDoc::new(
+            "test".into(),
+            Digest::SHA384,
+            JUNE_2000_MS,
+            pcrs,
+            END.to_vec(),
+            cabundle.iter().map(|der| der.to_vec()).collect(),
+            None,
+            None,
+            None,
+        )
+        .to_binary()
+    }
+
+    fn sign(payload: Vec<u8>) -> Vec<u8> {
+        let key = SigningKey::from_pkcs8_der(END_KEY).unwrap();
+        CoseSign1Builder::new()
+            .protected(
+                HeaderBuilder::new()
+                    .algorithm(iana::Algorithm::ES384)
+                    .build(),
+            )
+            .payload(payload)
+            .create_signature(b"", |data| {
+                let signature: Signature = key.sign(data);
+                signature.to_vec()
+            })
+            .build()
+            .to_vec()
+            .unwrap()
+    }
+
+    fn verify(document: &[u8], root_der: &[u8]) -> Result<AttestationDoc, Error> {
+        let root = parse_root(root_der)?;
+        let (cose, doc) = decode_document(document)?;
+        verify_chain_and_signature(&cose, &doc, &root)?;
+        Ok(doc)
+    }
+
+    #[test]
+    fn verifies_a_signed_document() {
+        let doc = verify(&sign(payload(&[ROOT, INT])), ROOT).unwrap();
+        assert_eq!(doc.module_id, "test");
+        // The cabundle need not repeat the root
+        assert!(verify(&sign(payload(&[INT])), ROOT).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_broken_chain() {
+        let document = sign(payload(&[ROOT, INT]));
+        assert_eq!(
+            verify(&document, OTHER_ROOT).err(),
+            Some(Error::ChainInvalid)
+        );
+        let missing_intermediate = sign(payload(&[ROOT]));
+        assert_eq!(
+            verify(&missing_intermediate, ROOT).err(),
+            Some(Error::ChainInvalid)
+        );
+        assert_eq!(
+            verify(&document, b"not DER").err(),
+            Some(Error::InvalidRoot)
+        );
+
+        // Chains to the root, but the second intermediate was not issued by the first
+        let gap = sign(payload(&[ROOT, INT, INT]));
+        assert_eq!(verify(&gap, ROOT).err(), Some(Error::ChainBroken));
+    }
+
+    #[test]
+    fn rejects_a_tampered_signature() {
+        let mut document = sign(payload(&[ROOT, INT]));
+        *document.last_mut().unwrap() ^= 1;
+        assert_eq!(verify(&document, ROOT).err(), Some(Error::SignatureInvalid));
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        assert_eq!(
+            decode_document(&[1]).err(),
+            Some(Error::MalformedDocument("not a COSE_Sign1 structure"))
+        );
+        let mut trailing = payload(&[ROOT, INT]);
+        trailing.push(0);
+        assert_eq!(
+            decode_document(&sign(trailing)).err(),
+            Some(Error::MalformedDocument("trailing bytes after payload"))
+        );
+
+        // A module_id of "test" with an invalid UTF-8 byte
+        let mut invalid_text = payload(&[ROOT, INT]);
+        let at = invalid_text
+            .windows(5)
+            .position(|window| window == b"\x64test")
+            .unwrap();
+        invalid_text[at + 1] = 0xff;
+        assert_eq!(
+            decode_document(&sign(invalid_text)).err(),
+            Some(Error::MalformedDocument("invalid UTF-8 in payload text"))
+        );
+    }
+
+    #[test]
+    fn checks_validity_windows() {
+        let root = parse_root(ROOT).unwrap();
+        let (_, doc) = decode_document(&sign(payload(&[ROOT, INT]))).unwrap();
+        assert_eq!(check_validity(&doc, &root, JUNE_2000_MS, 0), Ok(()));
+
+        let new_year_2000_ms = 946_684_800_000;
+        let before = new_year_2000_ms - DAY_MS / 2;
+        assert_eq!(
+            check_validity(&doc, &root, before, 0),
+            Err(Error::CertNotYetValid)
+        );
+        assert_eq!(check_validity(&doc, &root, before, DAY_MS), Ok(()));
+
+        let after = new_year_2000_ms + 367 * DAY_MS;
+        assert_eq!(
+            check_validity(&doc, &root, after, 0),
+            Err(Error::CertExpired)
+        );
+        assert_eq!(check_validity(&doc, &root, after, DAY_MS), Ok(()));
+    }
+
+    #[test]
+    fn checks_pcr_conventions() {
+        let (_, doc) = decode_document(&sign(payload(&[ROOT, INT]))).unwrap();
+        assert_eq!(check_pcr_conventions(&doc), Ok(()));
+
+        // 40 PCRs of 20 bytes each, as an unusual module might report
+        let unusual = (0..40).map(|index| (index, vec![0; 20])).collect();
+        let (_, doc) = decode_document(&sign(payload_with_pcrs(&[ROOT, INT], unusual))).unwrap();
+        assert_eq!(
+            check_pcr_conventions(&doc),
+            Err(Error::PcrAnomaly(PcrAnomaly::Count))
+        );
+        let short = BTreeMap::from([(0, vec![0; 48]), (1, vec![0; 32])]);
+        let (_, doc) = decode_document(&sign(payload_with_pcrs(&[ROOT, INT], short))).unwrap();
+        assert_eq!(
+            check_pcr_conventions(&doc),
+            Err(Error::PcrAnomaly(PcrAnomaly::LengthMismatch))
+        );
+        let out_of_range = BTreeMap::from([(32, vec![0; 48])]);
+        let (_, doc) =
+            decode_document(&sign(payload_with_pcrs(&[ROOT, INT], out_of_range))).unwrap();
+        assert_eq!(
+            check_pcr_conventions(&doc),
+            Err(Error::PcrAnomaly(PcrAnomaly::IndexOutOfRange))
+        );
+    }
+
+    #[test]
+    fn normalizes_public_keys() {
+        let key = SigningKey::from_pkcs8_der(END_KEY).unwrap();
+        let key = key.verifying_key();
+        let spki = key.to_public_key_der().unwrap().into_vec();
+        assert_eq!(normalize_public_key(&spki), Ok(spki.clone()));
+        let point = key.to_encoded_point(false);
+        assert_eq!(normalize_public_key(point.as_bytes()), Ok(spki.clone()));
+        let compressed = key.to_encoded_point(true);
+        assert_eq!(normalize_public_key(compressed.as_bytes()), Ok(spki));
+
+        assert_eq!(
+            normalize_public_key(b"not a key"),
+            Err(Error::MalformedDocument(
+                "public key is neither an SPKI nor a P-384 point"
+            ))
+        );
+    }
+}